@@ -0,0 +1,83 @@
+//! Compares the band hashing schemes, run with `cargo run --release --example band_hashing`.
+//! For each scheme it reports the insertion and query throughput, the estimated memory of the
+//! index, and the recall and precision of its candidates relative to `BandHashing::Values`, which
+//! stores the exact hash values of each band.
+use datasketch_minhash_lsh::{BandHashing, MinHash, MinHashLsh};
+use std::collections::HashSet;
+use std::time::Instant;
+
+const NUM_DOCUMENTS: u32 = 100_000;
+const NUM_PERM: usize = 128;
+
+fn main() {
+    let min_hashes: Vec<MinHash> = (0..NUM_DOCUMENTS)
+        .map(|i| {
+            let mut min_hash = <MinHash>::new(NUM_PERM, Some(42));
+            for value in i..i + 50 {
+                min_hash.update(&value);
+            }
+            min_hash
+        })
+        .collect();
+
+    // The first index built by the process pays for growing its heap, so build one untimed to
+    // compare the schemes fairly
+    let mut warm_up = <MinHashLsh<u32>>::new(NUM_PERM, None, Some(0.8)).unwrap();
+    for (i, min_hash) in min_hashes.iter().enumerate() {
+        warm_up.insert(i as u32, min_hash).unwrap();
+    }
+    drop(warm_up);
+
+    let mut exact_candidates: Vec<HashSet<u32>> = Vec::new();
+    for band_hashing in [
+        BandHashing::Values,
+        BandHashing::Digest64,
+        BandHashing::Digest128,
+    ] {
+        let mut lsh: MinHashLsh<u32> = MinHashLsh::builder()
+            .num_perm(NUM_PERM)
+            .threshold(0.8)
+            .band_hashing(band_hashing)
+            .build()
+            .unwrap();
+
+        let start = Instant::now();
+        for (i, min_hash) in min_hashes.iter().enumerate() {
+            lsh.insert(i as u32, min_hash).unwrap();
+        }
+        let insert_time = start.elapsed();
+        let memory = lsh.stats().estimated_memory_bytes;
+
+        let start = Instant::now();
+        let candidates: Vec<HashSet<u32>> = min_hashes
+            .iter()
+            .map(|min_hash| lsh.query(min_hash).unwrap())
+            .collect();
+        let query_time = start.elapsed();
+
+        if band_hashing == BandHashing::Values {
+            exact_candidates = candidates.clone();
+        }
+        let mut num_found = 0;
+        let mut num_candidates = 0;
+        let mut num_exact = 0;
+        for (candidates, exact) in candidates.iter().zip(&exact_candidates) {
+            num_found += candidates.intersection(exact).count();
+            num_candidates += candidates.len();
+            num_exact += exact.len();
+        }
+
+        println!(
+            "{:?}: inserted {} documents in {:?}, queried them in {:?}, estimated memory {:.1} MiB, \
+            {} candidates, recall {:.4} and precision {:.4} relative to Values",
+            band_hashing,
+            NUM_DOCUMENTS,
+            insert_time,
+            query_time,
+            memory as f64 / (1024.0 * 1024.0),
+            num_candidates,
+            num_found as f64 / num_exact as f64,
+            num_found as f64 / num_candidates as f64,
+        );
+    }
+}
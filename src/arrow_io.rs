@@ -0,0 +1,404 @@
+use crate::error::MinHashingError;
+use crate::minhash::{HashValues, MinHash};
+use crate::minhash_lsh::MinHashLsh;
+use arrow::array::{Array, ArrayRef, AsArray, FixedSizeListArray, UInt32Array, UInt64Array};
+use arrow::compute::{cast_with_options, CastOptions};
+use arrow::datatypes::{
+    ArrowPrimitiveType, DataType, Field, Int32Type, Int64Type, UInt32Type, UInt64Type,
+};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::file::reader::ChunkReader;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::hash::Hash;
+use std::sync::Arc;
+
+/// Field metadata key under which the seed of the signatures is stored
+pub const SEED_METADATA_KEY: &str = "minhash.seed";
+/// Field metadata key under which the number of permutation functions is stored
+pub const NUM_PERM_METADATA_KEY: &str = "minhash.num_perm";
+
+type Result<T> = std::result::Result<T, MinHashingError>;
+
+/// The Arrow value type used for the hash values of an exported signature column
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignatureType {
+    UInt32,
+    UInt64,
+}
+
+/// Convert a batch of MinHash signatures into an Arrow `FixedSizeList` column. The seed and the
+/// number of permutation functions are stored in the metadata of the returned field. Exporting as
+/// `UInt32` fails with `HashValueOutOfRange` if a hash value does not fit into 32 bits.
+pub fn signatures_to_arrow(
+    name: &str,
+    num_perm: usize,
+    seed: Option<u64>,
+    min_hashes: &[MinHash],
+    signature_type: SignatureType,
+) -> Result<(Field, FixedSizeListArray)> {
+    if num_perm < 2 {
+        return Err(MinHashingError::NumPermFuncsTooLow);
+    }
+    for min_hash in min_hashes {
+        if min_hash.num_perm() != num_perm {
+            return Err(MinHashingError::DifferentNumPermFuncs);
+        }
+        if min_hash.seed() != seed {
+            return Err(MinHashingError::DifferentSeeds);
+        }
+    }
+    let hash_values = min_hashes
+        .iter()
+        .flat_map(|min_hash| min_hash.hash_values.0.iter().copied());
+    let (item_type, values): (DataType, ArrayRef) = match signature_type {
+        SignatureType::UInt32 => {
+            let hash_values = hash_values
+                .map(|value| u32::try_from(value).map_err(|_| MinHashingError::HashValueOutOfRange))
+                .collect::<Result<Vec<u32>>>()?;
+            (DataType::UInt32, Arc::new(UInt32Array::from(hash_values)))
+        }
+        SignatureType::UInt64 => (
+            DataType::UInt64,
+            Arc::new(hash_values.collect::<UInt64Array>()),
+        ),
+    };
+    let item_field = Arc::new(Field::new("item", item_type, false));
+    let array = FixedSizeListArray::try_new(item_field.clone(), num_perm as i32, values, None)?;
+
+    let mut metadata = HashMap::new();
+    metadata.insert(NUM_PERM_METADATA_KEY.to_string(), num_perm.to_string());
+    if let Some(seed) = seed {
+        metadata.insert(SEED_METADATA_KEY.to_string(), seed.to_string());
+    }
+    let field = Field::new(
+        name,
+        DataType::FixedSizeList(item_field, num_perm as i32),
+        false,
+    )
+    .with_metadata(metadata);
+    Ok((field, array))
+}
+
+/// Convert an Arrow `FixedSizeList<UInt32/UInt64>` column created by `signatures_to_arrow` back
+/// into MinHash signatures
+pub fn signatures_from_arrow(field: &Field, array: &dyn Array) -> Result<Vec<MinHash>> {
+    let SignatureColumn {
+        num_perm,
+        seed,
+        hash_values,
+    } = SignatureColumn::try_new(field, array)?;
+    let min_hashes = hash_values
+        .chunks_exact(num_perm)
+        .map(|chunk| MinHash::from_hash_values(HashValues(chunk.to_vec()), seed))
+        .collect();
+    Ok(min_hashes)
+}
+
+/// Key types that `load_parquet` can read from a key column
+pub trait FromArrow: Sized {
+    /// Convert all entries of a key column, with `None` for nulls. Fails if the column has a type
+    /// that cannot be converted, or contains a value that does not fit into the key type.
+    fn from_arrow(array: &dyn Array) -> Result<Vec<Option<Self>>>;
+}
+
+impl FromArrow for String {
+    fn from_arrow(array: &dyn Array) -> Result<Vec<Option<String>>> {
+        let keys = cast_strict(array, &DataType::Utf8)?;
+        Ok(keys
+            .as_string::<i32>()
+            .iter()
+            .map(|key| key.map(str::to_string))
+            .collect())
+    }
+}
+
+macro_rules! impl_from_arrow_for_primitive {
+    ($($native:ty => $arrow:ty),*) => {$(
+        impl FromArrow for $native {
+            fn from_arrow(array: &dyn Array) -> Result<Vec<Option<$native>>> {
+                let keys = cast_strict(array, &<$arrow>::DATA_TYPE)?;
+                Ok(keys.as_primitive::<$arrow>().iter().collect())
+            }
+        }
+    )*};
+}
+
+impl_from_arrow_for_primitive!(i32 => Int32Type, i64 => Int64Type, u32 => UInt32Type, u64 => UInt64Type);
+
+// Unlike the default cast, fail on values that cannot be converted instead of turning them into
+// nulls
+fn cast_strict(array: &dyn Array, to_type: &DataType) -> Result<ArrayRef> {
+    let options = CastOptions {
+        safe: false,
+        ..Default::default()
+    };
+    Ok(cast_with_options(array, to_type, &options)?)
+}
+
+/// Bulk-load `(key, signature)` rows from a Parquet file into a MinHashLsh. The signature column
+/// must have been written from a field created by `signatures_to_arrow`, the key column may have
+/// any type that Arrow can cast to the key type, see `FromArrow`. Returns the number of inserted
+/// rows.
+pub fn load_parquet<KeyType, R>(
+    reader: R,
+    key_column: &str,
+    signature_column: &str,
+    lsh: &mut MinHashLsh<KeyType>,
+) -> Result<usize>
+where
+    KeyType: FromArrow + Eq + Hash + Clone,
+    R: ChunkReader + 'static,
+{
+    let batches = ParquetRecordBatchReaderBuilder::try_new(reader)?.build()?;
+    let mut num_rows = 0;
+    for batch in batches {
+        let batch = batch?;
+        let schema = batch.schema();
+        let (key_index, _) = schema
+            .column_with_name(key_column)
+            .ok_or_else(|| MinHashingError::MissingColumn(key_column.to_string()))?;
+        let (signature_index, signature_field) = schema
+            .column_with_name(signature_column)
+            .ok_or_else(|| MinHashingError::MissingColumn(signature_column.to_string()))?;
+        let keys = KeyType::from_arrow(batch.column(key_index).as_ref())?;
+        let SignatureColumn {
+            num_perm,
+            seed,
+            hash_values,
+        } = SignatureColumn::try_new(signature_field, batch.column(signature_index).as_ref())?;
+
+        // Reuse a single MinHash for all rows to avoid regenerating its permutation functions
+        let mut min_hash = MinHash::from_hash_values(HashValues(vec![0; num_perm]), seed);
+        for (row, (key, chunk)) in keys
+            .into_iter()
+            .zip(hash_values.chunks_exact(num_perm))
+            .enumerate()
+        {
+            let key = key.ok_or(MinHashingError::NullKey(num_rows + row))?;
+            min_hash.hash_values.0.copy_from_slice(chunk);
+            lsh.insert(key, &min_hash)?;
+        }
+        num_rows += batch.num_rows();
+    }
+    Ok(num_rows)
+}
+
+struct SignatureColumn {
+    num_perm: usize,
+    seed: Option<u64>,
+    hash_values: Vec<u64>,
+}
+
+impl SignatureColumn {
+    fn try_new(field: &Field, array: &dyn Array) -> Result<SignatureColumn> {
+        let metadata = field.metadata();
+        let num_perm: usize = metadata
+            .get(NUM_PERM_METADATA_KEY)
+            .and_then(|value| value.parse().ok())
+            .filter(|num_perm| *num_perm > 0)
+            .ok_or_else(|| {
+                MinHashingError::UnexpectedSignatureColumn(format!(
+                    "missing or invalid {} metadata",
+                    NUM_PERM_METADATA_KEY
+                ))
+            })?;
+        let seed = match metadata.get(SEED_METADATA_KEY) {
+            Some(value) => Some(value.parse().map_err(|_| {
+                MinHashingError::UnexpectedSignatureColumn(format!(
+                    "invalid {} metadata",
+                    SEED_METADATA_KEY
+                ))
+            })?),
+            None => None,
+        };
+
+        let list = array.as_fixed_size_list_opt().ok_or_else(|| {
+            MinHashingError::UnexpectedSignatureColumn("not a FixedSizeList".into())
+        })?;
+        if list.value_length() as usize != num_perm {
+            return Err(MinHashingError::DifferentNumPermFuncs);
+        }
+        if list.null_count() > 0 {
+            return Err(MinHashingError::UnexpectedSignatureColumn(
+                "null signatures are not supported".into(),
+            ));
+        }
+        let start = list.offset() * num_perm;
+        let values = list.values().slice(start, list.len() * num_perm);
+        let hash_values = match values.data_type() {
+            DataType::UInt32 => values
+                .as_primitive::<UInt32Type>()
+                .values()
+                .iter()
+                .map(|value| *value as u64)
+                .collect(),
+            DataType::UInt64 => values.as_primitive::<UInt64Type>().values().to_vec(),
+            other => {
+                return Err(MinHashingError::UnexpectedSignatureColumn(format!(
+                    "unsupported hash value type {}",
+                    other
+                )))
+            }
+        };
+        Ok(SignatureColumn {
+            num_perm,
+            seed,
+            hash_values,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use arrow::array::Int64Array;
+    use arrow::datatypes::Schema;
+    use arrow::record_batch::RecordBatch;
+    use parquet::arrow::ArrowWriter;
+    use std::fs::File;
+
+    fn min_hashes(num_perm: usize, seed: Option<u64>) -> Vec<MinHash> {
+        ["a", "b", "c"]
+            .iter()
+            .map(|value| {
+                let mut min_hash = <MinHash>::new(num_perm, seed);
+                min_hash.update(value);
+                min_hash
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_roundtrip() -> Result<()> {
+        for signature_type in [SignatureType::UInt32, SignatureType::UInt64] {
+            let originals = min_hashes(16, Some(3));
+            let (field, array) =
+                signatures_to_arrow("signature", 16, Some(3), &originals, signature_type)?;
+            assert_eq!(field.metadata()[SEED_METADATA_KEY], "3");
+            assert_eq!(field.metadata()[NUM_PERM_METADATA_KEY], "16");
+            let restored = signatures_from_arrow(&field, &array)?;
+            assert_eq!(restored.len(), originals.len());
+            for (mut original, restored) in originals.into_iter().zip(restored) {
+                assert_eq!(original.seed(), restored.seed());
+                assert_eq!(original.jaccard(&restored)?, 1.0);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_mismatching_signatures() {
+        let originals = min_hashes(16, Some(3));
+        let result =
+            signatures_to_arrow("signature", 8, Some(3), &originals, SignatureType::UInt32);
+        assert!(matches!(
+            result,
+            Err(MinHashingError::DifferentNumPermFuncs)
+        ));
+        let result = signatures_to_arrow("signature", 16, None, &originals, SignatureType::UInt32);
+        assert!(matches!(result, Err(MinHashingError::DifferentSeeds)));
+
+        let mut oversized = <MinHash>::new(16, Some(3));
+        oversized.hash_values.0[0] = 1 << 40;
+        let oversized = [oversized];
+        let result =
+            signatures_to_arrow("signature", 16, Some(3), &oversized, SignatureType::UInt32);
+        assert!(matches!(result, Err(MinHashingError::HashValueOutOfRange)));
+        let (field, array) =
+            signatures_to_arrow("signature", 16, Some(3), &oversized, SignatureType::UInt64)
+                .unwrap();
+        let restored = signatures_from_arrow(&field, &array).unwrap();
+        assert_eq!(restored[0].hash_values.0[0], 1 << 40);
+
+        let (field, array) =
+            signatures_to_arrow("signature", 16, Some(3), &originals, SignatureType::UInt32)
+                .unwrap();
+        let field = field.with_metadata(HashMap::new());
+        let result = signatures_from_arrow(&field, &array);
+        assert!(matches!(
+            result,
+            Err(MinHashingError::UnexpectedSignatureColumn(_))
+        ));
+
+        // An empty list type with a matching num_perm of 0 is not a valid signature column either
+        let item_field = Arc::new(Field::new("item", DataType::UInt32, false));
+        let values = Arc::new(UInt32Array::from(Vec::<u32>::new()));
+        let array = FixedSizeListArray::try_new(item_field.clone(), 0, values, None).unwrap();
+        let mut metadata = HashMap::new();
+        metadata.insert(NUM_PERM_METADATA_KEY.to_string(), "0".to_string());
+        let field = Field::new("signature", DataType::FixedSizeList(item_field, 0), false)
+            .with_metadata(metadata);
+        let result = signatures_from_arrow(&field, &array);
+        assert!(matches!(
+            result,
+            Err(MinHashingError::UnexpectedSignatureColumn(_))
+        ));
+    }
+
+    // Write the ids and the signatures of `originals` into a temporary Parquet file
+    fn write_parquet(name: &str, ids: ArrayRef, originals: &[MinHash]) -> std::path::PathBuf {
+        let (signature_field, signatures) =
+            signatures_to_arrow("signature", 16, Some(0), originals, SignatureType::UInt32)
+                .unwrap();
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", ids.data_type().clone(), true),
+            signature_field,
+        ]));
+        let batch = RecordBatch::try_new(schema.clone(), vec![ids, Arc::new(signatures)]).unwrap();
+        let path = std::env::temp_dir().join(format!(
+            "datasketch-minhash-lsh-{}-{}.parquet",
+            std::process::id(),
+            name
+        ));
+        let mut writer = ArrowWriter::try_new(File::create(&path).unwrap(), schema, None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+        path
+    }
+
+    #[test]
+    fn test_load_parquet() -> Result<()> {
+        let originals = min_hashes(16, Some(0));
+        let ids: ArrayRef = Arc::new(Int64Array::from(vec![1, 2, 3]));
+        let path = write_parquet("test_load_parquet", ids, &originals);
+
+        let mut lsh = <MinHashLsh<String>>::new(16, None, Some(0.5))?;
+        let num_rows = load_parquet(File::open(&path).unwrap(), "id", "signature", &mut lsh)?;
+        assert_eq!(num_rows, 3);
+        for (key, min_hash) in ["1", "2", "3"].iter().zip(&originals) {
+            let key = key.to_string();
+            assert!(lsh.contains_key(&key));
+            assert!(lsh.query(min_hash)?.contains(&key));
+        }
+
+        let mut lsh = <MinHashLsh<u32>>::new(16, None, Some(0.5))?;
+        load_parquet(File::open(&path).unwrap(), "id", "signature", &mut lsh)?;
+        std::fs::remove_file(&path).unwrap();
+        for (key, min_hash) in [1, 2, 3].iter().zip(&originals) {
+            assert!(lsh.query(min_hash)?.contains(key));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_parquet_invalid_keys() -> Result<()> {
+        let originals = min_hashes(16, Some(0));
+        let ids: ArrayRef = Arc::new(Int64Array::from(vec![Some(-1), None, Some(3)]));
+        let path = write_parquet("test_load_parquet_invalid_keys", ids, &originals);
+        let mut lsh = <MinHashLsh<i64>>::new(16, None, Some(0.5))?;
+        let result = load_parquet(File::open(&path).unwrap(), "key", "signature", &mut lsh);
+        assert!(matches!(result, Err(MinHashingError::MissingColumn(column)) if column == "key"));
+        let result = load_parquet(File::open(&path).unwrap(), "id", "signature", &mut lsh);
+        assert!(matches!(result, Err(MinHashingError::NullKey(1))));
+        assert!(lsh.contains_key(&-1));
+
+        // Negative ids do not fit into unsigned keys
+        let mut lsh = <MinHashLsh<u64>>::new(16, None, Some(0.5))?;
+        let result = load_parquet(File::open(&path).unwrap(), "id", "signature", &mut lsh);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(MinHashingError::Arrow(_))));
+        assert!(lsh.is_empty());
+        Ok(())
+    }
+}
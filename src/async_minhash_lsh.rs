@@ -0,0 +1,208 @@
+use crate::error::MinHashingError;
+use crate::minhash::MinHash;
+use crate::minhash_lsh::MinHashLsh;
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::{io, thread};
+use tokio::sync::{mpsc, oneshot};
+
+type Result<T> = std::result::Result<T, MinHashingError>;
+
+type Job<KeyType> = Box<dyn FnOnce(&mut MinHashLsh<KeyType>) + Send>;
+
+enum Command<KeyType: Eq + Hash + Clone> {
+    Run(Job<KeyType>),
+    Shutdown(oneshot::Sender<MinHashLsh<KeyType>>),
+}
+
+/// A handle to a MinHashLsh owned by a dedicated worker thread, for use from async code, e.g. in
+/// tokio services. Requests are applied one at a time in the order they were sent, so the index
+/// work never blocks the runtime. At most `queue_size` requests wait for the worker, further
+/// callers wait until there is room again. The handle can be cloned to share the worker between
+/// tasks. A request whose future is dropped after it was sent is still applied.
+pub struct AsyncMinHashLsh<KeyType: Eq + Hash + Clone> {
+    sender: mpsc::Sender<Command<KeyType>>,
+}
+
+impl<KeyType: Eq + Hash + Clone> Clone for AsyncMinHashLsh<KeyType> {
+    fn clone(&self) -> Self {
+        AsyncMinHashLsh {
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<KeyType: Eq + Hash + Clone + Send + 'static> AsyncMinHashLsh<KeyType> {
+    /// Move a MinHashLsh to a new worker thread. The worker stops once `shutdown` was called or
+    /// all handles were dropped, after applying the requests that were already sent. Fails if
+    /// `queue_size` is 0 or the thread cannot be started.
+    pub fn spawn(lsh: MinHashLsh<KeyType>, queue_size: usize) -> Result<AsyncMinHashLsh<KeyType>> {
+        if queue_size == 0 {
+            return Err(
+                io::Error::new(io::ErrorKind::InvalidInput, "queue_size must be positive").into(),
+            );
+        }
+        let (sender, receiver) = mpsc::channel(queue_size);
+        thread::Builder::new()
+            .name("minhash-lsh-worker".to_string())
+            .spawn(move || work(lsh, receiver))?;
+        Ok(AsyncMinHashLsh { sender })
+    }
+
+    /// Insert a new MinHash struct, see `MinHashLsh::insert`
+    pub async fn insert(&self, key: KeyType, min_hash: MinHash) -> Result<()> {
+        self.run(move |lsh| lsh.insert(key, &min_hash)).await
+    }
+
+    /// Remove the MinHash struct with a specific key, see `MinHashLsh::remove`
+    pub async fn remove(&self, key: KeyType) -> Result<()> {
+        self.run(move |lsh| lsh.remove(&key)).await
+    }
+
+    /// Query for candidates, see `MinHashLsh::query`
+    pub async fn query(&self, min_hash: MinHash) -> Result<HashSet<KeyType>> {
+        self.run(move |lsh| lsh.query(&min_hash)).await
+    }
+
+    /// Query for the `k` most similar MinHash structs, see `MinHashLsh::query_top_k`
+    pub async fn query_top_k(&self, min_hash: MinHash, k: usize) -> Result<Vec<(KeyType, f32)>>
+    where
+        KeyType: Ord,
+    {
+        self.run(move |lsh| lsh.query_top_k(&min_hash, k)).await
+    }
+
+    /// Stop the worker and return its MinHashLsh, e.g. to persist it. All requests sent before,
+    /// also through other handles, are applied first, later ones fail with `WorkerStopped`.
+    pub async fn shutdown(self) -> Result<MinHashLsh<KeyType>> {
+        let (reply, response) = oneshot::channel();
+        self.sender
+            .send(Command::Shutdown(reply))
+            .await
+            .map_err(|_| MinHashingError::WorkerStopped)?;
+        response.await.map_err(|_| MinHashingError::WorkerStopped)
+    }
+
+    async fn run<T, F>(&self, job: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut MinHashLsh<KeyType>) -> Result<T> + Send + 'static,
+    {
+        let (reply, response) = oneshot::channel();
+        let job: Job<KeyType> = Box::new(move |lsh| {
+            // The caller may have stopped waiting for the result
+            let _ = reply.send(job(lsh));
+        });
+        self.sender
+            .send(Command::Run(job))
+            .await
+            .map_err(|_| MinHashingError::WorkerStopped)?;
+        response.await.map_err(|_| MinHashingError::WorkerStopped)?
+    }
+}
+
+fn work<KeyType: Eq + Hash + Clone>(
+    mut lsh: MinHashLsh<KeyType>,
+    mut receiver: mpsc::Receiver<Command<KeyType>>,
+) {
+    let mut shutdown = None;
+    while let Some(command) = receiver.blocking_recv() {
+        match command {
+            Command::Run(job) => job(&mut lsh),
+            Command::Shutdown(reply) => {
+                // Reject new requests, but apply the ones that are already queued
+                receiver.close();
+                shutdown.get_or_insert(reply);
+            }
+        }
+    }
+    if let Some(reply) = shutdown {
+        let _ = reply.send(lsh);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::minhash::min_hash_of;
+
+    #[tokio::test]
+    async fn test_insert_query_remove() -> Result<()> {
+        let lsh = MinHashLsh::builder()
+            .num_perm(32)
+            .threshold(0.5)
+            .with_signatures()
+            .build()?;
+        let lsh = AsyncMinHashLsh::spawn(lsh, 4)?;
+        lsh.insert(1, min_hash_of(32, 0..20)).await?;
+        lsh.insert(2, min_hash_of(32, 2..22)).await?;
+        lsh.insert(3, min_hash_of(32, 1000..1020)).await?;
+        assert_eq!(
+            lsh.query(min_hash_of(32, 0..20)).await?,
+            vec![1, 2].into_iter().collect()
+        );
+        let top_k = lsh.query_top_k(min_hash_of(32, 0..20), 1).await?;
+        assert_eq!(top_k, vec![(1, 1.0)]);
+
+        lsh.remove(1).await?;
+        assert!(matches!(
+            lsh.remove(1).await,
+            Err(MinHashingError::KeyDoesNotExist)
+        ));
+        assert!(matches!(
+            lsh.insert(4, <MinHash>::new(16, Some(1))).await,
+            Err(MinHashingError::DifferentNumPermFuncs)
+        ));
+
+        let other = lsh.clone();
+        let lsh = lsh.shutdown().await?;
+        assert_eq!(lsh.len(), 2);
+        assert!(matches!(
+            other.query(min_hash_of(32, 0..20)).await,
+            Err(MinHashingError::WorkerStopped)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_spawn_without_queue() -> Result<()> {
+        let result = AsyncMinHashLsh::spawn(<MinHashLsh<u32>>::new(32, None, Some(0.5))?, 0);
+        assert!(matches!(
+            result,
+            Err(MinHashingError::Io(err)) if err.kind() == io::ErrorKind::InvalidInput
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_applies_pending_requests() -> Result<()> {
+        let lsh = AsyncMinHashLsh::spawn(<MinHashLsh<u32>>::new(32, None, Some(0.5))?, 2)?;
+        let tasks: Vec<_> = (0..50)
+            .map(|key| {
+                let lsh = lsh.clone();
+                tokio::spawn(async move {
+                    lsh.insert(key, min_hash_of(32, key * 100..key * 100 + 20))
+                        .await
+                })
+            })
+            .collect();
+        // Let some of the tasks queue their insertions before shutting down
+        tokio::task::yield_now().await;
+        let index = lsh.shutdown().await?;
+
+        // Every acknowledged insertion is contained, the others were rejected
+        let mut num_inserted = 0;
+        for (key, task) in tasks.into_iter().enumerate() {
+            match task.await.unwrap() {
+                Ok(()) => {
+                    assert!(index.contains_key(&(key as u32)));
+                    num_inserted += 1;
+                }
+                Err(err) => assert!(matches!(err, MinHashingError::WorkerStopped)),
+            }
+        }
+        assert!(num_inserted > 0);
+        assert_eq!(index.len(), num_inserted);
+        Ok(())
+    }
+}
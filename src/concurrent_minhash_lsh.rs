@@ -0,0 +1,250 @@
+use crate::error::MinHashingError;
+use crate::minhash::MinHash;
+use crate::minhash_lsh::{BandHashing, BandKey, LshParams, MinHashLsh, Weights};
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasher, Hash};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+const _NUM_KEY_SHARDS: usize = 16;
+
+type Result<T> = std::result::Result<T, MinHashingError>;
+
+/// A MinHashLsh that can be shared between threads, e.g. in an `Arc`, with `insert`, `remove` and
+/// `query` taking `&self`. Each hash table has its own lock, and the keys are split into shards
+/// with a lock each, so that queries only block while a writer updates the same band and writers
+/// only block each other for keys in the same shard. Operations on the same key are serialized by
+/// the lock of its shard, which keeps the keys and the hash tables consistent. A query running
+/// concurrently with an insertion or removal may see the key in some of its bands only.
+pub struct ConcurrentMinHashLsh<KeyType: Eq + Hash + Clone> {
+    num_perm: usize,
+    threshold: f64,
+    params: LshParams,
+    hash_ranges: Vec<(usize, usize)>,
+    hash_tables: Vec<RwLock<HashMap<BandKey, HashSet<KeyType>>>>,
+    key_shards: Vec<RwLock<HashMap<KeyType, Vec<BandKey>>>>,
+    hash_builder: RandomState,
+}
+
+impl<KeyType: Eq + Hash + Clone> ConcurrentMinHashLsh<KeyType> {
+    /// Build a new ConcurrentMinHashLsh struct with the same params as `MinHashLsh::new`.
+    /// Inserting a key that is already contained replaces its MinHash struct.
+    pub fn new(
+        num_perm: usize,
+        weights: Option<Weights>,
+        threshold: Option<f64>,
+    ) -> Result<ConcurrentMinHashLsh<KeyType>> {
+        let lsh = <MinHashLsh<KeyType>>::new(num_perm, weights, threshold)?;
+        let params = lsh.params().clone();
+        Ok(ConcurrentMinHashLsh {
+            num_perm,
+            threshold: lsh.threshold(),
+            hash_ranges: params.hash_ranges(),
+            hash_tables: (0..params.b).map(|_| RwLock::default()).collect(),
+            key_shards: (0.._NUM_KEY_SHARDS).map(|_| RwLock::default()).collect(),
+            params,
+            hash_builder: RandomState::new(),
+        })
+    }
+
+    /// The number of bands and the band size used by the ConcurrentMinHashLsh
+    pub fn params(&self) -> &LshParams {
+        &self.params
+    }
+
+    /// The configured threshold
+    pub fn threshold(&self) -> f64 {
+        self.threshold
+    }
+
+    /// The number of MinHash structs contained in the ConcurrentMinHashLsh
+    pub fn len(&self) -> usize {
+        self.key_shards.iter().map(|shard| read(shard).len()).sum()
+    }
+
+    /// Check whether the ConcurrentMinHashLsh contains any MinHash structs
+    pub fn is_empty(&self) -> bool {
+        self.key_shards.iter().all(|shard| read(shard).is_empty())
+    }
+
+    /// Checks whether a MinHash struct with a specific key is contained in the
+    /// ConcurrentMinHashLsh
+    pub fn contains_key(&self, key: &KeyType) -> bool {
+        read(self.key_shard(key)).contains_key(key)
+    }
+
+    /// Insert a new MinHash struct, replacing the one stored under the same key
+    pub fn insert(&self, key: KeyType, min_hash: &MinHash) -> Result<()> {
+        let hash_values = &min_hash.hash_values.0;
+        if hash_values.len() != self.num_perm {
+            return Err(MinHashingError::DifferentNumPermFuncs);
+        }
+        let hash_value_parts: Vec<BandKey> = self
+            .hash_ranges
+            .iter()
+            .map(|(start, end)| BandHashing::Values.band_key(&hash_values[*start..*end]))
+            .collect();
+        let mut keys = write(self.key_shard(&key));
+        if let Some(old_hash_value_parts) = keys.remove(&key) {
+            self.remove_from_tables(&key, &old_hash_value_parts);
+        }
+        for (hash_part, table) in hash_value_parts.iter().zip(&self.hash_tables) {
+            write(table)
+                .entry(hash_part.clone())
+                .or_insert_with(HashSet::new)
+                .insert(key.clone());
+        }
+        keys.insert(key, hash_value_parts);
+        Ok(())
+    }
+
+    /// Remove a MinHash struct with a specific key from the ConcurrentMinHashLsh
+    pub fn remove(&self, key: &KeyType) -> Result<()> {
+        let mut keys = write(self.key_shard(key));
+        let hash_value_parts = keys.remove(key).ok_or(MinHashingError::KeyDoesNotExist)?;
+        self.remove_from_tables(key, &hash_value_parts);
+        Ok(())
+    }
+
+    /// Query for candidates potentially within a jaccard-distance corresponding to the configured
+    /// threshold
+    pub fn query(&self, min_hash: &MinHash) -> Result<HashSet<KeyType>> {
+        let hash_values = &min_hash.hash_values.0;
+        if hash_values.len() != self.num_perm {
+            return Err(MinHashingError::DifferentNumPermFuncs);
+        }
+        let mut candidates = HashSet::new();
+        for ((start, end), table) in self.hash_ranges.iter().zip(&self.hash_tables) {
+            if let Some(bucket) = read(table).get(&hash_values[*start..*end]) {
+                candidates.extend(bucket.iter().cloned());
+            }
+        }
+        Ok(candidates)
+    }
+
+    fn remove_from_tables(&self, key: &KeyType, hash_value_parts: &[BandKey]) {
+        for (hash_part, table) in hash_value_parts.iter().zip(&self.hash_tables) {
+            let mut table = write(table);
+            if let Some(bucket) = table.get_mut(hash_part) {
+                bucket.remove(key);
+                if bucket.is_empty() {
+                    table.remove(hash_part);
+                }
+            }
+        }
+    }
+
+    fn key_shard(&self, key: &KeyType) -> &RwLock<HashMap<KeyType, Vec<BandKey>>> {
+        let hash = self.hash_builder.hash_one(key);
+        &self.key_shards[hash as usize % self.key_shards.len()]
+    }
+}
+
+// The locks are never held while user code runs, and an update that panicked midway can only have
+// added or removed a key from some of its buckets, so poisoned locks are still usable
+fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(|err| err.into_inner())
+}
+
+fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(|err| err.into_inner())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::minhash::min_hash_of;
+    use crate::minhash_lsh::assert_tables_consistent;
+    use std::thread;
+
+    fn assert_consistent(lsh: &ConcurrentMinHashLsh<u32>) {
+        let keys: HashMap<u32, Vec<BandKey>> = lsh
+            .key_shards
+            .iter()
+            .flat_map(|shard| read(shard).clone())
+            .collect();
+        let hash_tables: Vec<_> = lsh.hash_tables.iter().map(read).collect();
+        assert_tables_consistent(hash_tables.iter().map(|table| &**table), &keys, 0);
+    }
+
+    #[test]
+    fn test_insert_query_remove() -> Result<()> {
+        let lsh = <ConcurrentMinHashLsh<u32>>::new(32, None, Some(0.5))?;
+        assert!(lsh.is_empty());
+        lsh.insert(1, &min_hash_of(32, 0..20))?;
+        lsh.insert(2, &min_hash_of(32, 1000..1020))?;
+        lsh.insert(1, &min_hash_of(32, 2000..2020))?;
+        assert_eq!(lsh.len(), 2);
+        assert!(lsh.query(&min_hash_of(32, 2000..2020))?.contains(&1));
+        assert!(!lsh.query(&min_hash_of(32, 0..20))?.contains(&1));
+        assert_consistent(&lsh);
+
+        lsh.remove(&1)?;
+        assert!(!lsh.contains_key(&1));
+        assert!(matches!(
+            lsh.remove(&1),
+            Err(MinHashingError::KeyDoesNotExist)
+        ));
+        assert!(matches!(
+            lsh.insert(3, &<MinHash>::new(16, Some(1))),
+            Err(MinHashingError::DifferentNumPermFuncs)
+        ));
+        assert_consistent(&lsh);
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_writers_and_readers() -> Result<()> {
+        let lsh = <ConcurrentMinHashLsh<u32>>::new(32, None, Some(0.5))?;
+        let min_hashes: Vec<MinHash> = (0..50)
+            .map(|i| min_hash_of(32, i * 100..i * 100 + 20))
+            .collect();
+        thread::scope(|scope| {
+            for writer in 0..4u32 {
+                let lsh = &lsh;
+                let min_hashes = &min_hashes;
+                scope.spawn(move || {
+                    // The writers overlap on half of their keys
+                    for round in 0..20 {
+                        for key in (writer * 25)..(writer * 25 + 50) {
+                            let min_hash = &min_hashes[((key + round) % 50) as usize];
+                            lsh.insert(key, min_hash).unwrap();
+                            if (key + round) % 3 == 0 {
+                                // Another writer may have removed the key in between
+                                let _ = lsh.remove(&key);
+                            }
+                        }
+                    }
+                });
+            }
+            for reader in 0..4u32 {
+                let lsh = &lsh;
+                let min_hashes = &min_hashes;
+                scope.spawn(move || {
+                    // The keys of the readers share buckets with the ones of the writers, but are
+                    // never removed, so a finished insertion stays visible to all later queries
+                    let reader_key = |i: usize| 1000 + reader * 50 + i as u32;
+                    for (i, min_hash) in min_hashes.iter().enumerate() {
+                        lsh.insert(reader_key(i), min_hash).unwrap();
+                        for earlier in [i, i / 2] {
+                            let candidates = lsh.query(&min_hashes[earlier]).unwrap();
+                            assert!(candidates.contains(&reader_key(earlier)));
+                        }
+                    }
+                });
+            }
+        });
+        assert_consistent(&lsh);
+
+        // Every key ends up queryable with its last MinHash struct
+        for key in 0..125 {
+            lsh.insert(key, &min_hashes[(key % 50) as usize])?;
+        }
+        assert_eq!(lsh.len(), 125 + 4 * 50);
+        assert_consistent(&lsh);
+        for key in 0..125 {
+            assert!(lsh.query(&min_hashes[(key % 50) as usize])?.contains(&key));
+        }
+        Ok(())
+    }
+}
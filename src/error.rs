@@ -1,4 +1,5 @@
-use std::{error, fmt};
+use std::path::PathBuf;
+use std::{error, fmt, io};
 
 #[derive(Debug)]
 // https://doc.rust-lang.org/rust-by-example/error/multiple_error_types/wrap_error.html for from
@@ -10,12 +11,35 @@ pub enum MinHashingError {
     NumPermFuncsTooLow,
     WrongWeightThreshold,
     UnexpectedSumWeight,
+    WrongBandParams,
     KeyDoesNotExist,
+    KeyAlreadyExists,
+    MissingSeed,
+    MalformedBytes,
+    HashValueOutOfRange,
+    SignaturesNotStored,
+    EmptySignature,
+    BucketSizeExceeded,
+    CorruptedFile(PathBuf),
+    UnsupportedSnapshotVersion(PathBuf),
+    Io(io::Error),
+    #[cfg(feature = "async")]
+    WorkerStopped,
+    #[cfg(feature = "arrow")]
+    UnexpectedSignatureColumn(String),
+    #[cfg(feature = "arrow")]
+    MissingColumn(String),
+    #[cfg(feature = "arrow")]
+    NullKey(usize),
+    #[cfg(feature = "arrow")]
+    Arrow(arrow::error::ArrowError),
+    #[cfg(feature = "arrow")]
+    Parquet(parquet::errors::ParquetError),
 }
 
 impl fmt::Display for MinHashingError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
+        match self {
             MinHashingError::DifferentSeeds => write!(
                 f,
                 "computing jaccard similarity between minhashes only works if they \
@@ -30,9 +54,94 @@ impl fmt::Display for MinHashingError {
             MinHashingError::NumPermFuncsTooLow => write!(f, "Too few permutation functions"),
             MinHashingError::WrongWeightThreshold => write!(f, "Weight must be in [0.0, 1.0]"),
             MinHashingError::UnexpectedSumWeight => write!(f, "Weights must sum to 1.0"),
-            MinHashingError::KeyDoesNotExist => write!(f, "Attempted to remove a non-existing key"),
+            MinHashingError::WrongBandParams => {
+                write!(
+                    f,
+                    "b and r must be positive and b * r must not exceed num_perm"
+                )
+            }
+            MinHashingError::KeyDoesNotExist => write!(f, "Key does not exist"),
+            MinHashingError::KeyAlreadyExists => {
+                write!(f, "Attempted to insert an already existing key")
+            }
+            MinHashingError::MissingSeed => {
+                write!(f, "The minhash must have been created with a seed")
+            }
+            MinHashingError::MalformedBytes => write!(f, "Malformed serialized signature"),
+            MinHashingError::HashValueOutOfRange => {
+                write!(f, "Hash values must fit into 32 bits to be serialized")
+            }
+            MinHashingError::SignaturesNotStored => write!(
+                f,
+                "the MinHashLsh must store the signatures of all keys, see MinHashLsh::with_signatures"
+            ),
+            MinHashingError::EmptySignature => {
+                write!(f, "Attempted to insert a minhash that was never updated")
+            }
+            MinHashingError::BucketSizeExceeded => {
+                write!(
+                    f,
+                    "Inserting the minhash would exceed the maximum bucket size"
+                )
+            }
+            MinHashingError::CorruptedFile(path) => {
+                write!(f, "Corrupted index file {}", path.display())
+            }
+            MinHashingError::UnsupportedSnapshotVersion(path) => write!(
+                f,
+                "The snapshot {} was written in an unsupported format version",
+                path.display()
+            ),
+            MinHashingError::Io(err) => write!(f, "IO error: {}", err),
+            #[cfg(feature = "async")]
+            MinHashingError::WorkerStopped => {
+                write!(f, "The worker of the AsyncMinHashLsh has stopped")
+            }
+            #[cfg(feature = "arrow")]
+            MinHashingError::UnexpectedSignatureColumn(reason) => {
+                write!(f, "Unexpected signature column: {}", reason)
+            }
+            #[cfg(feature = "arrow")]
+            MinHashingError::MissingColumn(name) => write!(f, "Missing column {}", name),
+            #[cfg(feature = "arrow")]
+            MinHashingError::NullKey(row) => write!(f, "Null key in row {}", row),
+            #[cfg(feature = "arrow")]
+            MinHashingError::Arrow(err) => write!(f, "Arrow error: {}", err),
+            #[cfg(feature = "arrow")]
+            MinHashingError::Parquet(err) => write!(f, "Parquet error: {}", err),
+        }
+    }
+}
+
+impl error::Error for MinHashingError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            MinHashingError::Io(err) => Some(err),
+            #[cfg(feature = "arrow")]
+            MinHashingError::Arrow(err) => Some(err),
+            #[cfg(feature = "arrow")]
+            MinHashingError::Parquet(err) => Some(err),
+            _ => None,
         }
     }
 }
 
-impl error::Error for MinHashingError {}
+impl From<io::Error> for MinHashingError {
+    fn from(err: io::Error) -> Self {
+        MinHashingError::Io(err)
+    }
+}
+
+#[cfg(feature = "arrow")]
+impl From<arrow::error::ArrowError> for MinHashingError {
+    fn from(err: arrow::error::ArrowError) -> Self {
+        MinHashingError::Arrow(err)
+    }
+}
+
+#[cfg(feature = "arrow")]
+impl From<parquet::errors::ParquetError> for MinHashingError {
+    fn from(err: parquet::errors::ParquetError) -> Self {
+        MinHashingError::Parquet(err)
+    }
+}
@@ -0,0 +1,242 @@
+use crate::error::MinHashingError;
+use crate::minhash::{HashValues, MinHash};
+use itertools::Itertools;
+use std::convert::TryFrom;
+use std::convert::TryInto;
+
+// struct.calcsize("qi") in datasketch, the seed as i64 followed by num_perm as i32
+const _HEADER_SIZE: usize = 12;
+
+type Result<T> = std::result::Result<T, MinHashingError>;
+
+/// The byte order of serialized signatures, corresponding to the `byteorder` argument of
+/// `datasketch.LeanMinHash.serialize` (`@` is `Native`, `<` is `Little` and `>` is `Big`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ByteOrder {
+    Native,
+    Little,
+    Big,
+}
+
+/// An immutable MinHash signature that only holds the seed and the hash values, compatible with
+/// the byte layout of `datasketch.LeanMinHash`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LeanMinHash {
+    seed: u64,
+    hash_values: Vec<u32>,
+}
+
+impl LeanMinHash {
+    /// Build a new LeanMinHash struct from a seed and hash values
+    pub fn new(seed: u64, hash_values: Vec<u32>) -> LeanMinHash {
+        LeanMinHash { seed, hash_values }
+    }
+
+    /// The seed used to generate the permutation functions
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The number of permutation functions
+    pub fn num_perm(&self) -> usize {
+        self.hash_values.len()
+    }
+
+    /// The hash values of the signature
+    pub fn hash_values(&self) -> &[u32] {
+        &self.hash_values
+    }
+
+    /// Compute the jaccard distance between two LeanMinHash signatures that use the same seed and
+    /// number of permutation functions
+    pub fn jaccard(&self, other: &LeanMinHash) -> Result<f32> {
+        if other.seed != self.seed {
+            return Err(MinHashingError::DifferentSeeds);
+        }
+        if other.num_perm() != self.num_perm() {
+            return Err(MinHashingError::DifferentNumPermFuncs);
+        }
+        let matches = self
+            .hash_values
+            .iter()
+            .zip_eq(&other.hash_values)
+            .filter(|(left, right)| left == right)
+            .count();
+        Ok(matches as f32 / self.num_perm() as f32)
+    }
+
+    /// The number of bytes `serialize` produces
+    pub fn bytesize(&self) -> usize {
+        _HEADER_SIZE + 4 * self.num_perm()
+    }
+
+    /// Serialize into the byte layout of `datasketch.LeanMinHash.serialize`: the seed as i64, the
+    /// number of permutation functions as i32 and the hash values as u32
+    pub fn serialize(&self, byte_order: ByteOrder) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.bytesize());
+        self.serialize_into(&mut buf, byte_order);
+        buf
+    }
+
+    /// Append the serialized signature to a buffer, see `serialize`
+    pub fn serialize_into(&self, buf: &mut Vec<u8>, byte_order: ByteOrder) {
+        let seed = self.seed as i64;
+        let num_perm = self.num_perm() as i32;
+        match byte_order {
+            ByteOrder::Native => {
+                buf.extend_from_slice(&seed.to_ne_bytes());
+                buf.extend_from_slice(&num_perm.to_ne_bytes());
+                for value in &self.hash_values {
+                    buf.extend_from_slice(&value.to_ne_bytes());
+                }
+            }
+            ByteOrder::Little => {
+                buf.extend_from_slice(&seed.to_le_bytes());
+                buf.extend_from_slice(&num_perm.to_le_bytes());
+                for value in &self.hash_values {
+                    buf.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+            ByteOrder::Big => {
+                buf.extend_from_slice(&seed.to_be_bytes());
+                buf.extend_from_slice(&num_perm.to_be_bytes());
+                for value in &self.hash_values {
+                    buf.extend_from_slice(&value.to_be_bytes());
+                }
+            }
+        }
+    }
+
+    /// Parse a signature produced by `datasketch.LeanMinHash.serialize` (or by `serialize`).
+    /// Trailing bytes after the signature are ignored, as in datasketch.
+    pub fn deserialize(bytes: &[u8], byte_order: ByteOrder) -> Result<LeanMinHash> {
+        if bytes.len() < _HEADER_SIZE {
+            return Err(MinHashingError::MalformedBytes);
+        }
+        let seed = match byte_order {
+            ByteOrder::Native => i64::from_ne_bytes(bytes[0..8].try_into().unwrap()),
+            ByteOrder::Little => i64::from_le_bytes(bytes[0..8].try_into().unwrap()),
+            ByteOrder::Big => i64::from_be_bytes(bytes[0..8].try_into().unwrap()),
+        };
+        let num_perm = match byte_order {
+            ByteOrder::Native => i32::from_ne_bytes(bytes[8..12].try_into().unwrap()),
+            ByteOrder::Little => i32::from_le_bytes(bytes[8..12].try_into().unwrap()),
+            ByteOrder::Big => i32::from_be_bytes(bytes[8..12].try_into().unwrap()),
+        };
+        if num_perm < 0 || bytes.len() < _HEADER_SIZE + 4 * num_perm as usize {
+            return Err(MinHashingError::MalformedBytes);
+        }
+        let hash_values = bytes[_HEADER_SIZE.._HEADER_SIZE + 4 * num_perm as usize]
+            .chunks_exact(4)
+            .map(|chunk| {
+                let chunk = chunk.try_into().unwrap();
+                match byte_order {
+                    ByteOrder::Native => u32::from_ne_bytes(chunk),
+                    ByteOrder::Little => u32::from_le_bytes(chunk),
+                    ByteOrder::Big => u32::from_be_bytes(chunk),
+                }
+            })
+            .collect();
+        Ok(LeanMinHash {
+            seed: seed as u64,
+            hash_values,
+        })
+    }
+}
+
+impl TryFrom<&MinHash> for LeanMinHash {
+    type Error = MinHashingError;
+
+    /// Only MinHash structs with a seed can be converted, as datasketch always stores one
+    fn try_from(min_hash: &MinHash) -> Result<LeanMinHash> {
+        let seed = min_hash.seed().ok_or(MinHashingError::MissingSeed)?;
+        let hash_values = min_hash
+            .hash_values
+            .0
+            .iter()
+            .map(|value| u32::try_from(*value).map_err(|_| MinHashingError::HashValueOutOfRange))
+            .collect::<Result<_>>()?;
+        Ok(LeanMinHash { seed, hash_values })
+    }
+}
+
+impl From<&LeanMinHash> for MinHash {
+    /// The resulting MinHash can be used with `MinHash::jaccard` and `MinHashLsh`. Note that its
+    /// permutation functions differ from the ones datasketch derives from the same seed, so
+    /// further updates are not comparable to signatures computed in Python.
+    fn from(lean_min_hash: &LeanMinHash) -> MinHash {
+        let hash_values = lean_min_hash
+            .hash_values
+            .iter()
+            .map(|value| *value as u64)
+            .collect();
+        MinHash::from_hash_values(HashValues(hash_values), Some(lean_min_hash.seed))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::minhash_lsh::MinHashLsh;
+
+    // LeanMinHash(seed=1, hashvalues=[1, 2, 3]).serialize(buf, byteorder='<') in datasketch
+    const _DATASKETCH_LITTLE_ENDIAN: [u8; 24] = [
+        1, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0,
+    ];
+
+    #[test]
+    fn test_deserialize_datasketch_bytes() -> Result<()> {
+        let lean_min_hash =
+            LeanMinHash::deserialize(&_DATASKETCH_LITTLE_ENDIAN, ByteOrder::Little)?;
+        assert_eq!(lean_min_hash.seed(), 1);
+        assert_eq!(lean_min_hash.hash_values(), &[1, 2, 3]);
+        assert_eq!(lean_min_hash.bytesize(), _DATASKETCH_LITTLE_ENDIAN.len());
+        assert_eq!(
+            lean_min_hash.serialize(ByteOrder::Little),
+            _DATASKETCH_LITTLE_ENDIAN.to_vec()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_roundtrip() -> Result<()> {
+        let mut min_hash = <MinHash>::new(16, Some(7));
+        min_hash.update(&"a");
+        min_hash.update(&"b");
+        let lean_min_hash = LeanMinHash::try_from(&min_hash)?;
+        for byte_order in [ByteOrder::Native, ByteOrder::Little, ByteOrder::Big] {
+            let bytes = lean_min_hash.serialize(byte_order);
+            assert_eq!(bytes.len(), lean_min_hash.bytesize());
+            let restored = LeanMinHash::deserialize(&bytes, byte_order)?;
+            assert_eq!(restored, lean_min_hash);
+            assert_eq!(restored.jaccard(&lean_min_hash)?, 1.0);
+            assert_eq!(min_hash.jaccard(&MinHash::from(&restored))?, 1.0);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed_bytes() {
+        let result = LeanMinHash::deserialize(&_DATASKETCH_LITTLE_ENDIAN[..20], ByteOrder::Little);
+        assert!(matches!(result, Err(MinHashingError::MalformedBytes)));
+        let result = LeanMinHash::deserialize(&_DATASKETCH_LITTLE_ENDIAN[..8], ByteOrder::Little);
+        assert!(matches!(result, Err(MinHashingError::MalformedBytes)));
+
+        let min_hash = <MinHash>::new(16, None);
+        let result = LeanMinHash::try_from(&min_hash);
+        assert!(matches!(result, Err(MinHashingError::MissingSeed)));
+    }
+
+    #[test]
+    fn test_lsh_with_lean_min_hashes() -> Result<()> {
+        let mut m1 = <MinHash>::new(16, Some(1));
+        m1.update(&"a");
+        let bytes = LeanMinHash::try_from(&m1)?.serialize(ByteOrder::Native);
+
+        let mut lsh = <MinHashLsh<&str>>::new(16, None, Some(0.5))?;
+        let lean_min_hash = LeanMinHash::deserialize(&bytes, ByteOrder::Native)?;
+        lsh.insert("a", &MinHash::from(&lean_min_hash))?;
+        assert!(lsh.query(&m1)?.contains(&"a"));
+        Ok(())
+    }
+}
@@ -49,12 +49,32 @@
 use rand::prelude::SmallRng;
 use rand::{thread_rng, SeedableRng};
 
+#[cfg(feature = "arrow")]
+mod arrow_io;
+#[cfg(feature = "async")]
+mod async_minhash_lsh;
+mod concurrent_minhash_lsh;
 mod error;
+mod lean_minhash;
 mod minhash;
 mod minhash_lsh;
+mod minhash_lsh_ensemble;
+mod minhash_lsh_forest;
+#[cfg(feature = "rayon")]
+mod parallel;
+mod persistence;
 
+#[cfg(feature = "arrow")]
+pub use crate::arrow_io::*;
+#[cfg(feature = "async")]
+pub use crate::async_minhash_lsh::*;
+pub use crate::concurrent_minhash_lsh::*;
+pub use crate::lean_minhash::*;
 pub use crate::minhash::*;
 pub use crate::minhash_lsh::*;
+pub use crate::minhash_lsh_ensemble::*;
+pub use crate::minhash_lsh_forest::*;
+pub use crate::persistence::*;
 
 fn create_rng(seed: Option<u64>) -> SmallRng {
     match seed {
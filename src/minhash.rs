@@ -8,7 +8,7 @@ use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 
 const _MERSENNE_PRIME: u64 = (1 << 61) - 1;
-const _MAX_HASH: u64 = (1 << 32) - 1;
+pub(crate) const _MAX_HASH: u64 = (1 << 32) - 1;
 
 type Result<T> = std::result::Result<T, MinHashingError>;
 
@@ -23,7 +23,15 @@ pub struct MinHash {
     num_perm: usize,
     /// The HashValues corresponding to the set as it currently is
     pub hash_values: HashValues,
-    permutations: Vec<(u64, u64)>,
+    permutations: Permutations,
+}
+
+/// The parameters of the permutation functions `(a * h + b) mod p`, stored as separate arrays so
+/// that several of them can be evaluated at once
+#[derive(Clone, Debug, PartialEq)]
+struct Permutations {
+    a: Vec<u64>,
+    b: Vec<u64>,
 }
 
 impl MinHash {
@@ -39,36 +47,69 @@ impl MinHash {
         }
     }
 
+    /// Rebuild a MinHash struct from previously computed hash values, e.g. after loading them
+    /// from storage. Further updates are only consistent with the original if a seed was used.
+    pub fn from_hash_values(hash_values: HashValues, seed: Option<u64>) -> MinHash {
+        let num_perm = hash_values.0.len();
+        let permutations = Self::init_permutations(num_perm, seed);
+        MinHash {
+            seed,
+            num_perm,
+            hash_values,
+            permutations,
+        }
+    }
+
+    /// The seed used to generate the permutation functions
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// The number of permutation functions
+    pub fn num_perm(&self) -> usize {
+        self.num_perm
+    }
+
+    /// Check whether the MinHash struct was never updated, i.e. all hash values are still at their
+    /// initial maximum. Such MinHash structs are identical and collide with each other in MinHashLsh.
+    pub fn is_empty(&self) -> bool {
+        is_empty_signature(&self.hash_values.0)
+    }
+
     fn init_hash_values(num_perm: usize) -> HashValues {
         let vec = vec![_MAX_HASH; num_perm];
         HashValues(vec)
     }
 
-    fn init_permutations(num_perm: usize, seed: Option<u64>) -> Vec<(u64, u64)> {
+    fn init_permutations(num_perm: usize, seed: Option<u64>) -> Permutations {
         let rng = create_rng(seed);
         let distribution = Uniform::new(0, _MAX_HASH);
-        rng.sample_iter(distribution)
+        let (a, b) = rng
+            .sample_iter(distribution)
             .take(num_perm * 2)
             .tuples()
-            .collect_vec()
+            .unzip();
+        // The bounds of a and b keep the arithmetic in permute from overflowing
+        Permutations { a, b }
     }
 
-    /// Add a new value to the set
+    /// Add a new value to the set. Only x86_64 CPUs with AVX2 apply the permutation functions
+    /// with SIMD instructions, all other targets, including aarch64, use the scalar code.
     pub fn update<T: Hash>(&mut self, value_to_be_hashed: &T) {
         let mut hasher = DefaultHasher::new();
         value_to_be_hashed.hash(&mut hasher);
         let hash_value = hasher.finish() as u32 as u64;
         // TODO: Is there a better way to get u32 hashes?
-        let hash_value_permutations = self
-            .permutations
-            .iter()
-            .map(|(a, b)| (((a * hash_value) + b) % _MERSENNE_PRIME) & _MAX_HASH);
-        // np.min
-        self.hash_values
-            .0
-            .iter_mut()
-            .zip_eq(hash_value_permutations)
-            .for_each(|(old, new)| *old = min(*old, new));
+        let Permutations { a, b } = &self.permutations;
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx2") {
+                // Safety: the CPU supports AVX2
+                unsafe { update_avx2(a, b, hash_value, &mut self.hash_values.0) };
+                return;
+            }
+        }
+        update_scalar(a, b, hash_value, &mut self.hash_values.0);
     }
 
     /// Compute the jaccard distance between to MinHash sets that use the same seed and number of
@@ -96,6 +137,108 @@ impl MinHash {
     }
 }
 
+/// Reduce a value modulo the Mersenne prime 2^61 - 1 with a shift and an add instead of a division
+#[inline]
+fn mod_mersenne(value: u64) -> u64 {
+    // value = high * 2^61 + low = high + low (mod p), and high + low < p + 8
+    let reduced = (value & _MERSENNE_PRIME) + (value >> 61);
+    if reduced >= _MERSENNE_PRIME {
+        reduced - _MERSENNE_PRIME
+    } else {
+        reduced
+    }
+}
+
+/// Apply a permutation function to a hash value. The arithmetic wraps like numpy's uint64, which
+/// datasketch uses, and like the AVX2 path, so debug and release builds always agree. With `a` and
+/// `b` below 2^32 - 1 and the hash value below 2^32, `a * h + b` is at most 2^64 - 2^33 and never
+/// actually wraps, so the result is also the exact one.
+#[inline]
+fn permute(a: u64, b: u64, hash_value: u64) -> u64 {
+    mod_mersenne(a.wrapping_mul(hash_value).wrapping_add(b)) & _MAX_HASH
+}
+
+/// Apply the permutation functions to a hash value and keep the minimum of each
+fn update_scalar(a: &[u64], b: &[u64], hash_value: u64, hash_values: &mut [u64]) {
+    // np.min
+    hash_values
+        .iter_mut()
+        .zip_eq(a.iter().zip_eq(b))
+        .for_each(|(old, (a, b))| *old = min(*old, permute(*a, *b, hash_value)));
+}
+
+/// `update_scalar` for four permutation functions at a time. This is the only accelerated path,
+/// there is no NEON counterpart, so aarch64 and other targets always run `update_scalar`.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn update_avx2(a: &[u64], b: &[u64], hash_value: u64, hash_values: &mut [u64]) {
+    use std::arch::x86_64::*;
+
+    const LANES: usize = 4;
+    assert!(a.len() == hash_values.len() && b.len() == hash_values.len());
+    let vectorized = hash_values.len() - hash_values.len() % LANES;
+    let hash_value_lanes = _mm256_set1_epi64x(hash_value as i64);
+    let prime = _mm256_set1_epi64x(_MERSENNE_PRIME as i64);
+    let max_reduced = _mm256_set1_epi64x(_MERSENNE_PRIME as i64 - 1);
+    let max_hash = _mm256_set1_epi64x(_MAX_HASH as i64);
+    let sign_bit = _mm256_set1_epi64x(i64::MIN);
+    for offset in (0..vectorized).step_by(LANES) {
+        let a_lanes = _mm256_loadu_si256(a.as_ptr().add(offset) as *const __m256i);
+        let b_lanes = _mm256_loadu_si256(b.as_ptr().add(offset) as *const __m256i);
+        let old = _mm256_loadu_si256(hash_values.as_ptr().add(offset) as *const __m256i);
+        // a and the hash value are below 2^32, so multiplying the low halves of the lanes is exact
+        let value = _mm256_add_epi64(_mm256_mul_epu32(a_lanes, hash_value_lanes), b_lanes);
+        let reduced =
+            _mm256_add_epi64(_mm256_and_si256(value, prime), _mm256_srli_epi64(value, 61));
+        // The reduced values are below 2^62, so the signed comparison is exact
+        let overflow = _mm256_cmpgt_epi64(reduced, max_reduced);
+        let reduced = _mm256_sub_epi64(reduced, _mm256_and_si256(overflow, prime));
+        let new = _mm256_and_si256(reduced, max_hash);
+        // Flipping the sign bits turns the signed comparison into an unsigned one, as hash values
+        // restored with from_hash_values may use all 64 bits
+        let smaller = _mm256_cmpgt_epi64(
+            _mm256_xor_si256(old, sign_bit),
+            _mm256_xor_si256(new, sign_bit),
+        );
+        let result = _mm256_blendv_epi8(old, new, smaller);
+        _mm256_storeu_si256(hash_values.as_mut_ptr().add(offset) as *mut __m256i, result);
+    }
+    update_scalar(
+        &a[vectorized..],
+        &b[vectorized..],
+        hash_value,
+        &mut hash_values[vectorized..],
+    );
+}
+
+/// Check whether hash values are the ones of a MinHash struct that was never updated
+pub(crate) fn is_empty_signature(hash_values: &[u64]) -> bool {
+    hash_values.iter().all(|value| *value == _MAX_HASH)
+}
+
+/// Estimate the jaccard similarity of two sets from the hash values of their MinHash structs
+pub(crate) fn estimate_jaccard(left: &[u64], right: &[u64]) -> f32 {
+    let matches = left
+        .iter()
+        .zip_eq(right)
+        .filter(|(left, right)| left == right)
+        .count();
+    matches as f32 / left.len() as f32
+}
+
+/// Build a MinHash struct with seed 0 from a set of values, shared by the tests of the indexes
+#[cfg(test)]
+pub(crate) fn min_hash_of<T: Hash>(
+    num_perm: usize,
+    values: impl IntoIterator<Item = T>,
+) -> MinHash {
+    let mut min_hash = MinHash::new(num_perm, Some(0));
+    for value in values {
+        min_hash.update(&value);
+    }
+    min_hash
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -108,6 +251,21 @@ mod test {
         assert_eq!(m1.permutations, m2.permutations);
     }
 
+    #[test]
+    fn test_from_hash_values() -> Result<()> {
+        let mut m1 = <MinHash>::new(4, Some(1));
+        m1.update(&12);
+        let mut m2 = <MinHash>::from_hash_values(m1.hash_values.clone(), Some(1));
+        assert_eq!(m2.num_perm(), 4);
+        assert_eq!(m2.seed(), Some(1));
+        assert_eq!(m1.permutations, m2.permutations);
+        assert_eq!(m2.jaccard(&m1)?, 1.0);
+        m1.update(&13);
+        m2.update(&13);
+        assert_eq!(m1.hash_values.0, m2.hash_values.0);
+        Ok(())
+    }
+
     #[test]
     fn test_update() {
         let mut m1 = <MinHash>::new(4, Some(1));
@@ -118,6 +276,16 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_is_empty() {
+        let mut m1 = <MinHash>::new(4, Some(1));
+        assert!(m1.is_empty());
+        m1.update(&12);
+        assert!(!m1.is_empty());
+        let m2 = <MinHash>::from_hash_values(HashValues(vec![_MAX_HASH, 1]), Some(1));
+        assert!(!m2.is_empty());
+    }
+
     #[test]
     fn test_jaccard() -> Result<()> {
         let mut m1 = <MinHash>::new(4, Some(1));
@@ -144,4 +312,85 @@ mod test {
         assert_eq!(m.hash_values.0.len(), n_projections);
         println!("{:?}", &m.hash_values);
     }
+
+    #[test]
+    fn test_mod_mersenne() {
+        let mut rng = create_rng(Some(0));
+        let edge_cases = vec![
+            0,
+            1,
+            _MERSENNE_PRIME - 1,
+            _MERSENNE_PRIME,
+            _MERSENNE_PRIME + 1,
+            2 * _MERSENNE_PRIME,
+            u64::MAX - 1,
+            u64::MAX,
+        ];
+        let random = (0..10_000).map(|_| rng.gen::<u64>());
+        for value in edge_cases.into_iter().chain(random) {
+            assert_eq!(mod_mersenne(value), value % _MERSENNE_PRIME);
+        }
+    }
+
+    #[test]
+    fn test_update_scalar_fallback() {
+        let mut rng = create_rng(Some(0));
+        for num_perm in [1, 3, 4, 7, 128] {
+            let mut min_hash = <MinHash>::new(num_perm, Some(num_perm as u64));
+            // Restored hash values may be larger than the ones produced by update
+            min_hash.hash_values.0[0] = u64::MAX;
+            let mut scalar = min_hash.hash_values.0.clone();
+            let mut reference = min_hash.hash_values.0.clone();
+            let Permutations { a, b } = min_hash.permutations.clone();
+            for _ in 0..200 {
+                let value = rng.gen::<u64>();
+                min_hash.update(&value);
+
+                let mut hasher = DefaultHasher::new();
+                value.hash(&mut hasher);
+                let hash_value = hasher.finish() as u32 as u64;
+                update_scalar(&a, &b, hash_value, &mut scalar);
+                for ((old, a), b) in reference.iter_mut().zip(&a).zip(&b) {
+                    *old = min(*old, ((a * hash_value + b) % _MERSENNE_PRIME) & _MAX_HASH);
+                }
+                assert_eq!(min_hash.hash_values.0, scalar);
+                assert_eq!(min_hash.hash_values.0, reference);
+            }
+        }
+    }
+
+    #[test]
+    fn test_permute_exact() {
+        // permute only uses wrapping arithmetic, which behaves the same in debug and release
+        // builds, so comparing it with the exact u128 result covers both. The comparison fails if
+        // a * h + b ever exceeds u64::MAX, i.e. if the wrapping would change the result.
+        let mut rng = create_rng(None);
+        for _ in 0..100 {
+            // A different seed on every run, reported on failure
+            let seed = rng.gen::<u64>();
+            let min_hash = <MinHash>::new(128, Some(seed));
+            let Permutations { a, b } = &min_hash.permutations;
+            let hash_values = (0..100).map(|_| rng.gen::<u32>() as u64).chain(vec![
+                0,
+                1,
+                _MAX_HASH - 1,
+                _MAX_HASH,
+            ]);
+            for hash_value in hash_values {
+                for (a, b) in a.iter().zip(b) {
+                    let exact = *a as u128 * hash_value as u128 + *b as u128;
+                    assert!(exact <= u64::MAX as u128, "seed {}", seed);
+                    let expected = (exact % _MERSENNE_PRIME as u128) as u64 & _MAX_HASH;
+                    assert_eq!(permute(*a, *b, hash_value), expected, "seed {}", seed);
+                }
+            }
+        }
+        // The largest possible parameters stay exact as well
+        let (a, b) = (_MAX_HASH - 1, _MAX_HASH - 1);
+        let exact = a as u128 * _MAX_HASH as u128 + b as u128;
+        assert_eq!(
+            permute(a, b, _MAX_HASH),
+            (exact % _MERSENNE_PRIME as u128) as u64 & _MAX_HASH
+        );
+    }
 }
@@ -1,20 +1,130 @@
 use crate::error::MinHashingError;
-use crate::minhash::MinHash;
+use crate::minhash::{estimate_jaccard, is_empty_signature, HashValues, MinHash};
 use float_cmp::ApproxEq;
 use quadrature::integrate;
+use std::borrow::Borrow;
+use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
-use std::hash::Hash;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::mem::size_of;
 
-const _ALLOWED_INTEGRATE_ERR: f64 = 0.001;
+pub(crate) const _ALLOWED_INTEGRATE_ERR: f64 = 0.001;
+// The number of the closest buckets `query_top_k` probes when the bands yield fewer than k candidates
+const _DEFAULT_PROBE_BUDGET: usize = 10_000;
 
 /// The weights configuring whether to prefer false positives or false negatives
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct Weights(pub f64, pub f64);
 
 /// A part of a HashValue used in MinHashLsh
 #[derive(Clone, Debug, Hash, PartialEq, Eq)]
 pub struct HashValuePart(pub Vec<u64>);
 
+/// The key of a band in the hash tables, i.e. the hash values of the band or a digest of them
+/// depending on the `BandHashing`. It hashes and compares like the slice of its words, so that
+/// hash tables can be queried with a borrowed `&[u64]` without allocating.
+#[derive(Clone, Debug)]
+pub(crate) enum BandKey {
+    Values(Vec<u64>),
+    Digest64([u64; 1]),
+    Digest128([u64; 2]),
+}
+
+impl BandKey {
+    /// The hash values or the words of the digest
+    pub(crate) fn as_slice(&self) -> &[u64] {
+        match self {
+            BandKey::Values(values) => values,
+            BandKey::Digest64(digest) => digest,
+            BandKey::Digest128(digest) => digest,
+        }
+    }
+}
+
+impl Hash for BandKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_slice().hash(state)
+    }
+}
+
+impl PartialEq for BandKey {
+    fn eq(&self, other: &BandKey) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl Eq for BandKey {}
+
+impl Borrow<[u64]> for BandKey {
+    fn borrow(&self) -> &[u64] {
+        self.as_slice()
+    }
+}
+
+/// How MinHashLsh stores the hash values of a band in its hash tables and key map.
+///
+/// `Values` keeps all `r` hash values of a band in a heap-allocated vector. The digests replace
+/// them with a single 64-bit or 128-bit hash stored inline, which saves the heap allocation of `r`
+/// hash values, i.e. `r * 8` bytes plus allocator overhead, per band and key in the key map and per
+/// bucket in the hash tables. The inline part of each stored band keeps its size of 24 bytes in all
+/// schemes, so the savings grow with `r`. Two different bands share a digest with a probability of
+/// about 2^-64 (2^-128 for `Digest128`), in which case their keys end up in the same bucket. Such
+/// collisions can only add false positive candidates, they never hide a true candidate. With `n`
+/// distinct bands in a hash table, the expected number of colliding pairs is about n^2 / 2^65 for
+/// `Digest64`, e.g. 0.03 for a billion distinct bands, and negligible for `Digest128`. As digests
+/// do not retain individual hash values, `query_top_k` cannot probe buckets that differ from the
+/// query in only a few hash values, and only returns the candidates of the matching bands.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BandHashing {
+    #[default]
+    Values,
+    Digest64,
+    Digest128,
+}
+
+impl BandHashing {
+    /// Store the hash values of a band according to this scheme
+    pub(crate) fn band_key(&self, values: &[u64]) -> BandKey {
+        match self {
+            BandHashing::Values => BandKey::Values(values.to_vec()),
+            BandHashing::Digest64 => BandKey::Digest64([digest64(values, _DIGEST_SEEDS[0])]),
+            BandHashing::Digest128 => BandKey::Digest128([
+                digest64(values, _DIGEST_SEEDS[0]),
+                digest64(values, _DIGEST_SEEDS[1]),
+            ]),
+        }
+    }
+
+    /// Look up the bucket of a band in a hash table without allocating
+    fn get<'a, V>(&self, table: &'a HashMap<BandKey, V>, values: &[u64]) -> Option<&'a V> {
+        match self {
+            BandHashing::Values => table.get(values),
+            BandHashing::Digest64 => table.get(&[digest64(values, _DIGEST_SEEDS[0])][..]),
+            BandHashing::Digest128 => table.get(
+                &[
+                    digest64(values, _DIGEST_SEEDS[0]),
+                    digest64(values, _DIGEST_SEEDS[1]),
+                ][..],
+            ),
+        }
+    }
+}
+
+const _DIGEST_SEEDS: [u64; 2] = [0x243f_6a88_85a3_08d3, 0x1319_8a2e_0370_7344];
+
+/// Combine hash values into a 64-bit digest. Each step applies the splitmix64 finalizer, which is
+/// a bijection, so two bands with the same digest prefix state still differ after a differing
+/// hash value.
+fn digest64(values: &[u64], seed: u64) -> u64 {
+    values.iter().fold(seed, |digest, value| {
+        let mut x = digest.wrapping_add(0x9e37_79b9_7f4a_7c15) ^ value;
+        x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        x ^ (x >> 31)
+    })
+}
+
 /// The LSH params for the number of bands and the band size
 #[derive(Clone, Debug)]
 pub struct LshParams {
@@ -30,8 +140,8 @@ impl LshParams {
         for b in 1..num_perm + 1 {
             let max_r = num_perm / b;
             for r in 1..max_r + 1 {
-                let false_pos = LshParams::false_positive_probability(threshold, b, r);
-                let false_neg = LshParams::false_negative_probability(threshold, b, r);
+                let false_pos = LshParams::false_positive_probability(threshold, b, r, 1);
+                let false_neg = LshParams::false_negative_probability(threshold, b, r, 1);
                 let error = false_pos * false_positive_weight + false_neg * false_negative_weight;
                 if error < min_error {
                     min_error = error;
@@ -42,33 +152,268 @@ impl LshParams {
         opt
     }
 
-    fn false_positive_probability(threshold: f64, b: usize, r: usize) -> f64 {
-        let _probability =
-            |s| -> f64 { 1. - f64::powf(1. - f64::powi(s, r as i32) as f64, b as f64) };
+    /// The expected false positive probability of these params, i.e. the probability that a set
+    /// with a jaccard similarity below the threshold collides in at least one band, integrated
+    /// over all similarities below the threshold
+    pub fn false_positive_rate(&self, threshold: f64) -> f64 {
+        LshParams::false_positive_probability(threshold, self.b, self.r, 1)
+    }
+
+    /// The expected false negative probability of these params, i.e. the probability that a set
+    /// with a jaccard similarity above the threshold collides in no band, integrated over all
+    /// similarities above the threshold
+    pub fn false_negative_rate(&self, threshold: f64) -> f64 {
+        LshParams::false_negative_probability(threshold, self.b, self.r, 1)
+    }
+
+    /// The ranges of the hash values that make up each of the `b` bands of size `r`
+    pub(crate) fn hash_ranges(&self) -> Vec<(usize, usize)> {
+        (0..self.b)
+            .map(|i| (i * self.r, (i + 1) * self.r))
+            .collect()
+    }
+
+    /// Find the number of bands out of `b` bands of size `r` that have to match to best
+    /// approximate `threshold`, e.g. for querying with a different threshold than the one the
+    /// params were optimized for
+    pub fn find_optimal_min_matches(
+        threshold: f64,
+        b: usize,
+        r: usize,
+        weights: &Weights,
+    ) -> usize {
+        let Weights(false_positive_weight, false_negative_weight) = weights;
+        let mut min_error = f64::INFINITY;
+        let mut opt = 1;
+        for min_matches in 1..b + 1 {
+            let false_pos = LshParams::false_positive_probability(threshold, b, r, min_matches);
+            let false_neg = LshParams::false_negative_probability(threshold, b, r, min_matches);
+            let error = false_pos * false_positive_weight + false_neg * false_negative_weight;
+            if error < min_error {
+                min_error = error;
+                opt = min_matches;
+            }
+        }
+        opt
+    }
+
+    /// The probability that at least `min_matches` out of `b` bands of size `r` collide for two
+    /// sets with jaccard similarity `s`
+    fn collision_probability(s: f64, b: usize, r: usize, min_matches: usize) -> f64 {
+        let band_probability = f64::powi(s, r as i32);
+        if min_matches <= 1 {
+            return 1. - f64::powf(1. - band_probability, b as f64);
+        }
+        // 1 - P(fewer than min_matches bands collide), with binomially distributed collisions
+        let mut binomial_coefficient = 1.;
+        let mut fewer_matches = 0.;
+        for i in 0..min_matches.min(b + 1) {
+            if i > 0 {
+                binomial_coefficient *= (b - i + 1) as f64 / i as f64;
+            }
+            fewer_matches += binomial_coefficient
+                * f64::powi(band_probability, i as i32)
+                * f64::powi(1. - band_probability, (b - i) as i32);
+        }
+        1. - fewer_matches
+    }
+
+    fn false_positive_probability(threshold: f64, b: usize, r: usize, min_matches: usize) -> f64 {
+        let _probability = |s| -> f64 { LshParams::collision_probability(s, b, r, min_matches) };
         integrate(_probability, 0.0, threshold, _ALLOWED_INTEGRATE_ERR).integral
     }
 
-    fn false_negative_probability(threshold: f64, b: usize, r: usize) -> f64 {
+    fn false_negative_probability(threshold: f64, b: usize, r: usize, min_matches: usize) -> f64 {
         let _probability =
-            |s| -> f64 { 1. - (1. - f64::powf(1. - f64::powi(s, r as i32), b as f64)) };
+            |s| -> f64 { 1. - LshParams::collision_probability(s, b, r, min_matches) };
         integrate(_probability, threshold, 1.0, _ALLOWED_INTEGRATE_ERR).integral
     }
 }
 
+/// How `MinHashLsh::insert` handles keys that are already contained in the MinHashLsh
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicateKeyPolicy {
+    /// Fail with `MinHashingError::KeyAlreadyExists`
+    Error,
+    /// Remove the previously inserted MinHash struct before inserting the new one
+    #[default]
+    Replace,
+    /// Keep the previously inserted MinHash struct
+    Ignore,
+}
+
+/// How `MinHashLsh::insert` handles MinHash structs that were never updated, see
+/// `MinHash::is_empty`. All of them share the same hash values, so they collide with each other in
+/// every band.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EmptySignaturePolicy {
+    /// Index them like any other MinHash struct
+    #[default]
+    Allow,
+    /// Fail with `MinHashingError::EmptySignature`
+    Reject,
+    /// Keep their keys, but never return them as candidates
+    Isolate,
+    /// Keep their keys in a dedicated bucket outside of the hash tables, so that they are only
+    /// candidates of queries with an empty MinHash struct
+    Bucket,
+}
+
+/// How `MinHashLsh::insert` handles buckets that would exceed the configured maximum size
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BucketOverflowPolicy {
+    /// Insert anyway and log a warning when a bucket first exceeds the maximum size
+    Warn,
+    /// Fail with `MinHashingError::BucketSizeExceeded` without inserting
+    Error,
+}
+
 /// The MinHashLsh struct
 #[derive(Clone)]
 pub struct MinHashLsh<KeyType: Eq + Hash + Clone> {
+    pub(crate) num_perm: usize,
+    pub(crate) threshold: f64,
+    pub(crate) weights: Weights,
+    pub(crate) buffer_size: usize,
+    pub(crate) duplicate_key_policy: DuplicateKeyPolicy,
+    pub(crate) band_hashing: BandHashing,
+    pub(crate) empty_signature_policy: EmptySignaturePolicy,
+    pub(crate) max_bucket_size: Option<(usize, BucketOverflowPolicy)>,
+    pub(crate) params: LshParams,
+    pub(crate) hash_tables: Vec<HashMap<BandKey, HashSet<KeyType>>>,
+    hash_ranges: Vec<(usize, usize)>,
+    pub(crate) keys: HashMap<KeyType, Vec<BandKey>>,
+    // The keys of empty MinHash structs kept outside of the hash tables
+    pub(crate) empty_keys: HashSet<KeyType>,
+    pub(crate) signatures: Option<HashMap<KeyType, HashValues>>,
+}
+
+type Result<T> = std::result::Result<T, MinHashingError>;
+
+/// A builder for MinHashLsh structs, see `MinHashLsh::builder`
+#[derive(Clone)]
+pub struct MinHashLshBuilder<KeyType: Eq + Hash + Clone> {
     num_perm: usize,
     threshold: f64,
     weights: Weights,
+    bands: Option<LshParams>,
     buffer_size: usize,
-    params: LshParams,
-    hash_tables: Vec<HashMap<HashValuePart, HashSet<KeyType>>>,
-    hash_ranges: Vec<(usize, usize)>,
-    keys: HashMap<KeyType, Vec<HashValuePart>>,
+    duplicate_key_policy: DuplicateKeyPolicy,
+    band_hashing: BandHashing,
+    empty_signature_policy: EmptySignaturePolicy,
+    max_bucket_size: Option<(usize, BucketOverflowPolicy)>,
+    store_signatures: bool,
+    key_type: PhantomData<KeyType>,
 }
 
-type Result<T> = std::result::Result<T, MinHashingError>;
+impl<KeyType: Eq + Hash + Clone> MinHashLshBuilder<KeyType> {
+    /// The number of permutation functions of the inserted MinHash structs, 128 by default
+    pub fn num_perm(mut self, num_perm: usize) -> Self {
+        self.num_perm = num_perm;
+        self
+    }
+
+    /// The jaccard similarity threshold, 0.9 by default
+    pub fn threshold(mut self, threshold: f64) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// The weights of false positives and false negatives used to optimize the params for the
+    /// threshold, `Weights(0.5, 0.5)` by default
+    pub fn weights(mut self, weights: Weights) -> Self {
+        self.weights = weights;
+        self
+    }
+
+    /// Use `b` bands of size `r` instead of optimizing them for the threshold
+    pub fn bands(mut self, b: usize, r: usize) -> Self {
+        self.bands = Some(LshParams { b, r });
+        self
+    }
+
+    /// The number of MinHash structs an insertion session buffers before applying them to the
+    /// hash tables, 50000 by default
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        self.buffer_size = buffer_size;
+        self
+    }
+
+    /// How to handle inserting a key that is already contained, `DuplicateKeyPolicy::Replace` by
+    /// default
+    pub fn duplicate_key_policy(mut self, duplicate_key_policy: DuplicateKeyPolicy) -> Self {
+        self.duplicate_key_policy = duplicate_key_policy;
+        self
+    }
+
+    /// How to store the hash values of the bands, `BandHashing::Values` by default
+    pub fn band_hashing(mut self, band_hashing: BandHashing) -> Self {
+        self.band_hashing = band_hashing;
+        self
+    }
+
+    /// How to handle MinHash structs that were never updated, `EmptySignaturePolicy::Allow` by
+    /// default
+    pub fn empty_signature_policy(mut self, empty_signature_policy: EmptySignaturePolicy) -> Self {
+        self.empty_signature_policy = empty_signature_policy;
+        self
+    }
+
+    /// Limit the number of keys per bucket, handling insertions into full buckets according to
+    /// the overflow policy. Buckets are unlimited by default.
+    pub fn max_bucket_size(mut self, max_bucket_size: usize, policy: BucketOverflowPolicy) -> Self {
+        self.max_bucket_size = Some((max_bucket_size, policy));
+        self
+    }
+
+    /// Keep the full signatures of inserted MinHash structs, see `MinHashLsh::with_signatures`
+    pub fn with_signatures(mut self) -> Self {
+        self.store_signatures = true;
+        self
+    }
+
+    /// Validate the configuration and build the MinHashLsh struct
+    pub fn build(self) -> Result<MinHashLsh<KeyType>> {
+        if !(0.0..=1.0).contains(&self.threshold) {
+            return Err(MinHashingError::WrongThresholdInterval);
+        }
+        if self.num_perm < 2 {
+            return Err(MinHashingError::NumPermFuncsTooLow);
+        }
+        let weights = validate_weights(self.weights)?;
+        let params = match self.bands {
+            Some(LshParams { b, r }) if b == 0 || r == 0 || b * r > self.num_perm => {
+                return Err(MinHashingError::WrongBandParams);
+            }
+            Some(params) => params,
+            None => LshParams::find_optimal_params(self.threshold, self.num_perm, &weights),
+        };
+        let mut lsh = MinHashLsh::with_params(self.num_perm, self.threshold, weights, params);
+        lsh.buffer_size = self.buffer_size.max(1);
+        lsh.duplicate_key_policy = self.duplicate_key_policy;
+        lsh.band_hashing = self.band_hashing;
+        lsh.empty_signature_policy = self.empty_signature_policy;
+        lsh.max_bucket_size = self.max_bucket_size;
+        if self.store_signatures {
+            Ok(lsh.with_signatures())
+        } else {
+            Ok(lsh)
+        }
+    }
+}
+
+/// Check that the weights of false positives and false negatives are valid
+pub(crate) fn validate_weights(weights: Weights) -> Result<Weights> {
+    let Weights(left, right) = weights;
+    if !(0.0..=1.0).contains(&left) || !(0.0..=1.0).contains(&right) {
+        return Err(MinHashingError::WrongWeightThreshold);
+    }
+    let sum_weights = left + right;
+    if !sum_weights.approx_eq(1.0, (0.0, 2)) {
+        return Err(MinHashingError::UnexpectedSumWeight);
+    }
+    Ok(weights)
+}
 
 impl<KeyType: Eq + Hash + Clone> MinHashLsh<KeyType> {
     /// Build a new MinHashLsh struct
@@ -77,80 +422,320 @@ impl<KeyType: Eq + Hash + Clone> MinHashLsh<KeyType> {
         weights: Option<Weights>,
         threshold: Option<f64>,
     ) -> Result<MinHashLsh<KeyType>> {
-        let threshold = match threshold {
-            Some(threshold) if !(0.0..=1.0).contains(&threshold) => {
-                return Err(MinHashingError::WrongThresholdInterval);
-            }
-            Some(threshold) => threshold,
-            _ => 0.9,
-        };
-        if num_perm < 2 {
-            return Err(MinHashingError::NumPermFuncsTooLow);
+        let mut builder = Self::builder().num_perm(num_perm);
+        if let Some(weights) = weights {
+            builder = builder.weights(weights);
         }
+        if let Some(threshold) = threshold {
+            builder = builder.threshold(threshold);
+        }
+        builder.build()
+    }
 
-        let weights = match weights {
-            Some(weights) => {
-                let Weights(left, right) = weights;
-                if !(0.0..=1.0).contains(&left) || !(0.0..=1.0).contains(&right) {
-                    return Err(MinHashingError::WrongWeightThreshold);
-                }
-                let sum_weights = left + right;
-                if !sum_weights.approx_eq(1.0, (0.0, 2)) {
-                    return Err(MinHashingError::UnexpectedSumWeight);
-                }
-                weights
-            }
-            _ => Weights(0.5, 0.5),
-        };
-        let params = LshParams::find_optimal_params(threshold, num_perm, &weights);
+    /// Configure a new MinHashLsh struct, either with a threshold and weights from which the
+    /// params are optimized as in `new`, or with explicit params
+    pub fn builder() -> MinHashLshBuilder<KeyType> {
+        MinHashLshBuilder {
+            num_perm: 128,
+            threshold: 0.9,
+            weights: Weights(0.5, 0.5),
+            bands: None,
+            buffer_size: 50_000,
+            duplicate_key_policy: DuplicateKeyPolicy::default(),
+            band_hashing: BandHashing::default(),
+            empty_signature_policy: EmptySignaturePolicy::default(),
+            max_bucket_size: None,
+            store_signatures: false,
+            key_type: PhantomData,
+        }
+    }
 
-        let hash_tables = (0..params.b).into_iter().map(|_| HashMap::new()).collect();
-        let hash_ranges = (0..params.b)
-            .into_iter()
-            .map(|i| (i * params.r, (i + 1) * params.r))
-            .collect();
-        Ok(MinHashLsh {
+    /// Build a new MinHashLsh struct from already validated params
+    pub(crate) fn with_params(
+        num_perm: usize,
+        threshold: f64,
+        weights: Weights,
+        params: LshParams,
+    ) -> MinHashLsh<KeyType> {
+        let hash_tables = (0..params.b).map(|_| HashMap::new()).collect();
+        let hash_ranges = params.hash_ranges();
+        MinHashLsh {
             num_perm,
             threshold,
             weights,
             buffer_size: 50_000,
+            duplicate_key_policy: DuplicateKeyPolicy::default(),
+            band_hashing: BandHashing::default(),
+            empty_signature_policy: EmptySignaturePolicy::default(),
+            max_bucket_size: None,
             params,
             hash_tables,
             hash_ranges,
-            keys: HashMap::<KeyType, Vec<HashValuePart>>::new(),
-        })
+            keys: HashMap::<KeyType, Vec<BandKey>>::new(),
+            empty_keys: HashSet::new(),
+            signatures: None,
+        }
+    }
+
+    /// Keep the full signatures of inserted MinHash structs in the index, which allows
+    /// `query_verified` to filter out candidates below the threshold. Only MinHash structs inserted
+    /// afterwards are stored, so this should be enabled on an empty MinHashLsh. Queries that need
+    /// the signature of a key inserted before fail with `SignaturesNotStored`.
+    pub fn with_signatures(mut self) -> MinHashLsh<KeyType> {
+        if self.signatures.is_none() {
+            self.signatures = Some(HashMap::new());
+        }
+        self
+    }
+
+    /// The number of bands and the band size used by the MinHashLsh
+    pub fn params(&self) -> &LshParams {
+        &self.params
+    }
+
+    /// The configured threshold
+    pub fn threshold(&self) -> f64 {
+        self.threshold
+    }
+
+    /// The configured weights of false positives and false negatives
+    pub fn weights(&self) -> &Weights {
+        &self.weights
+    }
+
+    /// How inserting a key that is already contained is handled
+    pub fn duplicate_key_policy(&self) -> DuplicateKeyPolicy {
+        self.duplicate_key_policy
+    }
+
+    /// How the hash values of the bands are stored
+    pub fn band_hashing(&self) -> BandHashing {
+        self.band_hashing
+    }
+
+    /// How MinHash structs that were never updated are handled
+    pub fn empty_signature_policy(&self) -> EmptySignaturePolicy {
+        self.empty_signature_policy
+    }
+
+    /// The expected false positive probability for the configured threshold and params
+    pub fn false_positive_rate(&self) -> f64 {
+        self.params.false_positive_rate(self.threshold)
+    }
+
+    /// The expected false negative probability for the configured threshold and params
+    pub fn false_negative_rate(&self) -> f64 {
+        self.params.false_negative_rate(self.threshold)
     }
 
     /// Check whether the MinHashLsh contains any MinHash structs
     pub fn is_empty(&self) -> bool {
-        self.hash_tables.iter().any(|table| table.len() == 0)
+        self.keys.is_empty()
+    }
+
+    /// The number of MinHash structs contained in the MinHashLsh
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Iterate over the keys of the contained MinHash structs together with their band parts, in
+    /// arbitrary order. A band part is made of the hash values of the band, or the words of their
+    /// digest if the bands are stored as digests, see `BandHashing`.
+    pub fn iter(&self) -> impl Iterator<Item = (&KeyType, impl Iterator<Item = &[u64]>)> {
+        self.keys
+            .iter()
+            .map(|(key, hash_value_parts)| (key, hash_value_parts.iter().map(BandKey::as_slice)))
     }
 
-    /// Insert a new MinHash struct
+    /// Insert a new MinHash struct. Keys that are already contained are handled according to the
+    /// configured `DuplicateKeyPolicy`.
     pub fn insert(&mut self, key: KeyType, min_hash: &MinHash) -> Result<()> {
-        // TODO: We could also add optional checks whether the key is already present in index
-        // TODO: Why has the original implementation buffer params everywhere
-        if min_hash.hash_values.0.len() != self.num_perm {
+        self.insert_hash_values(key, &min_hash.hash_values.0)
+    }
+
+    /// Start an insertion session for bulk loading many MinHash structs. The session buffers up
+    /// to `buffer_size` MinHash structs and applies them to the hash tables in one pass per band.
+    /// Buffered MinHash structs cannot be queried before the session is flushed or dropped. With a
+    /// `max_bucket_size`, and for empty MinHash structs unless they are allowed, the session
+    /// flushes and inserts one MinHash struct at a time like `insert` instead, as these depend on
+    /// the current buckets.
+    pub fn insertion_session(&mut self) -> InsertionSession<'_, KeyType> {
+        InsertionSession {
+            buffer: Vec::with_capacity(self.buffer_size.min(1024)),
+            buffered_keys: HashMap::new(),
+            lsh: self,
+        }
+    }
+
+    /// Check whether a MinHash struct can be inserted according to the configured policies,
+    /// without changing the MinHashLsh. Returns whether inserting it changes the MinHashLsh.
+    pub(crate) fn check_insert(&self, key: &KeyType, hash_values: &[u64]) -> Result<bool> {
+        self.check_hash_values(hash_values)?;
+        if self.keys.contains_key(key) && !self.accepts_duplicate()? {
+            return Ok(false);
+        }
+        if let Some((max_bucket_size, BucketOverflowPolicy::Error)) = self.max_bucket_size {
+            if !self.is_special_empty(hash_values)
+                && self.exceeds_bucket_size(key, hash_values, max_bucket_size)
+            {
+                return Err(MinHashingError::BucketSizeExceeded);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Check the number of hash values and the empty signature policy
+    pub(crate) fn check_hash_values(&self, hash_values: &[u64]) -> Result<()> {
+        if hash_values.len() != self.num_perm {
             return Err(MinHashingError::DifferentNumPermFuncs);
         }
-        let mut hash_value_parts: Vec<HashValuePart> = self
-            .hash_ranges
+        if self.empty_signature_policy == EmptySignaturePolicy::Reject
+            && is_empty_signature(hash_values)
+        {
+            return Err(MinHashingError::EmptySignature);
+        }
+        Ok(())
+    }
+
+    /// Apply the duplicate key policy to a key that is already contained. Returns whether the
+    /// key should be replaced.
+    pub(crate) fn accepts_duplicate(&self) -> Result<bool> {
+        match self.duplicate_key_policy {
+            DuplicateKeyPolicy::Error => Err(MinHashingError::KeyAlreadyExists),
+            DuplicateKeyPolicy::Replace => Ok(true),
+            DuplicateKeyPolicy::Ignore => Ok(false),
+        }
+    }
+
+    /// Whether the hash values are the ones of an empty MinHash struct that the configured policy
+    /// keeps out of the hash tables
+    pub(crate) fn is_special_empty(&self, hash_values: &[u64]) -> bool {
+        self.empty_signature_policy != EmptySignaturePolicy::Allow
+            && is_empty_signature(hash_values)
+    }
+
+    /// Whether inserting the hash values under a key would grow a bucket beyond the maximum size
+    fn exceeds_bucket_size(&self, key: &KeyType, hash_values: &[u64], max_size: usize) -> bool {
+        self.hash_ranges
             .iter()
-            .map(|(start, end)| {
-                let hash_part = min_hash.hash_values.0[*start..*end].to_owned();
-                HashValuePart(hash_part)
+            .zip(&self.hash_tables)
+            .any(|((start, end), table)| {
+                match self.band_hashing.get(table, &hash_values[*start..*end]) {
+                    Some(bucket) => bucket.len() >= max_size && !bucket.contains(key),
+                    None => max_size == 0,
+                }
             })
-            .collect();
+    }
+
+    /// Split hash values into the parts covered by each band
+    pub(crate) fn hash_value_parts(&self, hash_values: &[u64]) -> Vec<BandKey> {
+        self.hash_ranges
+            .iter()
+            .map(|(start, end)| self.band_hashing.band_key(&hash_values[*start..*end]))
+            .collect()
+    }
+
+    /// Insert the hash values of a MinHash struct, e.g. when replaying them from storage
+    pub(crate) fn insert_hash_values(&mut self, key: KeyType, hash_values: &[u64]) -> Result<()> {
+        if !self.check_insert(&key, hash_values)? {
+            return Ok(());
+        }
+        if self.keys.contains_key(&key) {
+            self.remove(&key)?;
+        }
+        let hash_value_parts = self.hash_value_parts(hash_values);
+        if let Some(signatures) = &mut self.signatures {
+            signatures.insert(key.clone(), HashValues(hash_values.to_vec()));
+        }
+        if self.is_special_empty(hash_values) {
+            self.insert_empty_hash_value_parts(key, hash_value_parts);
+        } else {
+            self.insert_hash_value_parts(key, hash_value_parts);
+        }
+        Ok(())
+    }
+
+    /// Keep the band parts of an empty MinHash struct outside of the hash tables
+    pub(crate) fn insert_empty_hash_value_parts(
+        &mut self,
+        key: KeyType,
+        hash_value_parts: Vec<BandKey>,
+    ) {
+        self.keys.insert(key.clone(), hash_value_parts);
+        self.empty_keys.insert(key);
+    }
+
+    /// Insert the already banded hash values of a MinHash struct
+    pub(crate) fn insert_hash_value_parts(
+        &mut self,
+        key: KeyType,
+        mut hash_value_parts: Vec<BandKey>,
+    ) {
         self.keys.insert(key.clone(), hash_value_parts.clone());
         let hash_table_iter = &mut self.hash_tables.iter_mut();
         let zipped_drain_iter = hash_value_parts.drain(..).zip(hash_table_iter);
-        for (hash_part, hash_table) in zipped_drain_iter {
-            hash_table
-                .entry(hash_part)
-                .or_insert_with(HashSet::new)
-                .insert(key.clone());
+        for (band, (hash_part, hash_table)) in zipped_drain_iter.enumerate() {
+            let bucket = hash_table.entry(hash_part).or_insert_with(HashSet::new);
+            bucket.insert(key.clone());
+            warn_if_oversized(self.max_bucket_size, band, bucket.len());
         }
-        Ok(())
+    }
+
+    /// Insert a new MinHash struct or update the one stored under an existing key. Only the hash
+    /// tables of bands whose hash values changed are touched. Returns the number of bands that
+    /// moved to a different bucket, which is the number of bands for a new key.
+    pub fn upsert(&mut self, key: KeyType, min_hash: &MinHash) -> Result<usize> {
+        let hash_values = &min_hash.hash_values.0;
+        if hash_values.len() != self.num_perm {
+            return Err(MinHashingError::DifferentNumPermFuncs);
+        }
+        let is_empty = self.is_special_empty(hash_values);
+        if is_empty && self.empty_signature_policy == EmptySignaturePolicy::Reject {
+            return Err(MinHashingError::EmptySignature);
+        }
+        if let Some((max_bucket_size, BucketOverflowPolicy::Error)) = self.max_bucket_size {
+            if !is_empty && self.exceeds_bucket_size(&key, hash_values, max_bucket_size) {
+                return Err(MinHashingError::BucketSizeExceeded);
+            }
+        }
+        // Moving from or to the bucket of empty MinHash structs changes all bands
+        if !self.keys.contains_key(&key) || self.empty_keys.contains(&key) || is_empty {
+            if self.keys.contains_key(&key) {
+                self.remove(&key)?;
+            }
+            self.insert_hash_values(key, hash_values)?;
+            return Ok(self.params.b);
+        }
+        let new_hash_value_parts = self.hash_value_parts(hash_values);
+        let hash_value_parts = self.keys.get_mut(&key).unwrap();
+        let mut num_moved = 0;
+        for (band, ((hash_part, new_hash_part), table)) in hash_value_parts
+            .iter_mut()
+            .zip(new_hash_value_parts)
+            .zip(&mut self.hash_tables)
+            .enumerate()
+        {
+            if *hash_part == new_hash_part {
+                continue;
+            }
+            if let Some(set) = table.get_mut(hash_part) {
+                set.remove(&key);
+                if set.is_empty() {
+                    table.remove(hash_part);
+                }
+            }
+            let bucket = table
+                .entry(new_hash_part.clone())
+                .or_insert_with(HashSet::new);
+            bucket.insert(key.clone());
+            warn_if_oversized(self.max_bucket_size, band, bucket.len());
+            *hash_part = new_hash_part;
+            num_moved += 1;
+        }
+        if let Some(signatures) = &mut self.signatures {
+            signatures.insert(key, HashValues(hash_values.clone()));
+        }
+        Ok(num_moved)
     }
 
     /// Checks whether a MinHash struct with a specific key is contained in the MinHashLsh
@@ -163,6 +748,13 @@ impl<KeyType: Eq + Hash + Clone> MinHashLsh<KeyType> {
         if !self.keys.contains_key(key) {
             return Err(MinHashingError::KeyDoesNotExist);
         }
+        if self.empty_keys.remove(key) {
+            self.keys.remove(key);
+            if let Some(signatures) = &mut self.signatures {
+                signatures.remove(key);
+            }
+            return Ok(());
+        }
         for (hash_part, table) in self
             .keys
             .get_mut(key)
@@ -178,6 +770,9 @@ impl<KeyType: Eq + Hash + Clone> MinHashLsh<KeyType> {
             }
         }
         self.keys.remove(key);
+        if let Some(signatures) = &mut self.signatures {
+            signatures.remove(key);
+        }
         Ok(())
     }
 
@@ -188,65 +783,833 @@ impl<KeyType: Eq + Hash + Clone> MinHashLsh<KeyType> {
             .map(|table| {
                 table
                     .iter()
-                    .map(|(key, value)| (key.clone(), value.len()))
+                    .map(|(key, value)| (HashValuePart(key.as_slice().to_vec()), value.len()))
                     .collect()
             })
             .collect()
     }
 
+    /// Summarize the hash tables, e.g. to spot pathologically large buckets that slow down queries,
+    /// without cloning keys. See `LshStats` for the reported numbers.
+    pub fn stats(&self) -> LshStats {
+        let mut bands = Vec::with_capacity(self.hash_tables.len());
+        let mut bucket_size_histogram = Vec::new();
+        let mut largest: Vec<(usize, usize, &BandKey)> = Vec::new();
+        let mut memory = self.keys.capacity()
+            * (size_of::<KeyType>() + size_of::<Vec<BandKey>>() + 1)
+            + self.keys.len() * self.params.b * size_of::<BandKey>();
+        for (band, table) in self.hash_tables.iter().enumerate() {
+            let mut band_stats = BandStats::default();
+            memory += table.capacity() * (size_of::<BandKey>() + size_of::<HashSet<KeyType>>() + 1);
+            for (hash_part, bucket) in table {
+                let size = bucket.len();
+                band_stats.num_buckets += 1;
+                band_stats.num_keys += size;
+                band_stats.max_bucket_size = band_stats.max_bucket_size.max(size);
+                memory += bucket.capacity() * (size_of::<KeyType>() + 1);
+
+                let size_class = (usize::BITS - size.leading_zeros()) as usize - 1;
+                if bucket_size_histogram.len() <= size_class {
+                    bucket_size_histogram.resize(size_class + 1, 0);
+                }
+                bucket_size_histogram[size_class] += 1;
+
+                if largest.len() < _NUM_LARGEST_BUCKETS || size > largest[largest.len() - 1].0 {
+                    let position = largest.partition_point(|(other, _, _)| *other >= size);
+                    largest.insert(position, (size, band, hash_part));
+                    largest.truncate(_NUM_LARGEST_BUCKETS);
+                }
+            }
+            bands.push(band_stats);
+        }
+        if let Some(BandKey::Values(_)) = self.keys.values().flatten().next() {
+            // The hash values of each band are stored both in the key map and the hash tables
+            let num_parts = self.keys.len() * self.params.b
+                + bands.iter().map(|band| band.num_buckets).sum::<usize>();
+            memory += num_parts * self.params.r * size_of::<u64>();
+        }
+        memory += self.empty_keys.capacity() * (size_of::<KeyType>() + 1);
+        if let Some(signatures) = &self.signatures {
+            memory += signatures.capacity() * (size_of::<KeyType>() + size_of::<HashValues>() + 1)
+                + signatures.len() * self.num_perm * size_of::<u64>();
+        }
+        LshStats {
+            num_keys: self.keys.len(),
+            num_empty_keys: self.empty_keys.len(),
+            bands,
+            bucket_size_histogram,
+            largest_buckets: largest
+                .into_iter()
+                .map(|(size, band, hash_part)| LargeBucket {
+                    band,
+                    size,
+                    hash_value_part: HashValuePart(hash_part.as_slice().to_vec()),
+                })
+                .collect(),
+            estimated_memory_bytes: memory,
+        }
+    }
+
     /// Query for candidates potentially within a jaccard-distance corresponding to the configured
     /// threshold
-    pub fn query(&mut self, min_hash: &MinHash) -> Result<HashSet<KeyType>> {
-        if min_hash.hash_values.0.len() != self.num_perm {
-            return Err(MinHashingError::DifferentNumPermFuncs);
+    pub fn query(&self, min_hash: &MinHash) -> Result<HashSet<KeyType>> {
+        self.query_candidates(min_hash)
+    }
+
+    /// Query for candidates like `query`, but collect them into an existing set, which is cleared
+    /// first. Reusing the set across queries avoids allocating a new one for every query.
+    pub fn query_into(&self, min_hash: &MinHash, candidates: &mut HashSet<KeyType>) -> Result<()> {
+        candidates.clear();
+        self.for_each_band_bucket(min_hash, self.params.b, |bucket| {
+            candidates.extend(bucket.iter().cloned())
+        })
+    }
+
+    /// Call `f` with the keys of the candidates `query` would return, without cloning keys or
+    /// allocating. Candidates that collide with the MinHash struct in several bands are passed to
+    /// `f` once per band.
+    pub fn for_each_candidate<'a, F>(&'a self, min_hash: &MinHash, mut f: F) -> Result<()>
+    where
+        F: FnMut(&'a KeyType),
+    {
+        self.for_each_band_bucket(min_hash, self.params.b, |bucket| {
+            bucket.iter().for_each(&mut f)
+        })
+    }
+
+    /// Query for candidates like `query` for the MinHash struct stored under a key, using its
+    /// stored band parts. If `exclude_self` is set, the key itself is not part of the result.
+    pub fn query_by_key(&self, key: &KeyType, exclude_self: bool) -> Result<HashSet<KeyType>> {
+        let hash_value_parts = self.keys.get(key).ok_or(MinHashingError::KeyDoesNotExist)?;
+        if self.empty_keys.contains(key) {
+            let mut candidates = match self.empty_signature_policy {
+                EmptySignaturePolicy::Bucket => self.empty_keys.clone(),
+                _ => HashSet::new(),
+            };
+            if exclude_self {
+                candidates.remove(key);
+            }
+            return Ok(candidates);
         }
-        let unique_candidates = self
-            .hash_ranges
+        let mut candidates: HashSet<KeyType> = hash_value_parts
             .iter()
             .zip(&self.hash_tables)
-            .flat_map(|(range, table)| {
-                let (start, end) = range;
-                let hash_part = min_hash.hash_values.0[*start..*end].to_owned();
-                table.get(&HashValuePart(hash_part))
-            })
+            .filter_map(|(hash_part, table)| table.get(hash_part))
             .flatten()
             .cloned()
             .collect();
-        Ok(unique_candidates)
+        if exclude_self {
+            candidates.remove(key);
+        }
+        Ok(candidates)
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use crate::minhash::MinHash;
+    /// Query for candidates like `query`, but only return the candidates whose jaccard similarity
+    /// estimated from their stored signature reaches the configured threshold, together with that
+    /// estimate. Requires signatures to be stored, see `with_signatures`.
+    pub fn query_verified(&self, min_hash: &MinHash) -> Result<Vec<(KeyType, f32)>> {
+        let signatures = self
+            .signatures
+            .as_ref()
+            .ok_or(MinHashingError::SignaturesNotStored)?;
+        let mut verified = Vec::new();
+        for candidate in self.query_candidates(min_hash)? {
+            let signature = stored_signature(signatures, &candidate)?;
+            let jaccard = estimate_jaccard(&min_hash.hash_values.0, &signature.0);
+            if jaccard as f64 >= self.threshold {
+                verified.push((candidate, jaccard));
+            }
+        }
+        Ok(verified)
+    }
 
-    #[test]
-    fn test_init() -> Result<()> {
-        let lsh = <MinHashLsh<&str>>::new(128, None, Some(0.8))?;
-        assert!(lsh.is_empty());
-        let LshParams { b: b1, r: r1 } = lsh.params;
-        let lsh = <MinHashLsh<&str>>::new(128, Some(Weights(0.2, 0.8)), Some(0.8))?;
-        let LshParams { b: b2, r: r2 } = lsh.params;
-        assert!(b1 < b2);
-        assert!(r1 > r2);
-        Ok(())
+    /// Query for candidates potentially within a jaccard-distance corresponding to `threshold`
+    /// instead of the configured threshold. The bands are fixed when building the index, so a
+    /// candidate has to collide with the query in a number of bands that best approximates the
+    /// threshold. As at least one band has to match, this can only be stricter than the configured
+    /// threshold, so build the index with a low threshold (i.e., small bands) to allow for a wide
+    /// range of query thresholds. The number of bands is derived from the threshold on every call,
+    /// so use `min_matches_for` and `query_with_min_matches` to query repeatedly with the same
+    /// threshold.
+    pub fn query_with_threshold(
+        &self,
+        min_hash: &MinHash,
+        threshold: f64,
+    ) -> Result<HashSet<KeyType>> {
+        let min_matches = self.min_matches_for(threshold)?;
+        self.query_with_min_matches(min_hash, min_matches)
     }
 
-    #[test]
-    fn test_insert() -> Result<()> {
-        let mut lsh = <MinHashLsh<&str>>::new(128, None, Some(0.5))?;
-        let mut m1 = <MinHash>::new(128, Some(0));
-        m1.update(&"a");
-        let mut m2 = <MinHash>::new(128, Some(0));
-        m2.update(&"b");
-        lsh.insert("a", &m1)?;
-        lsh.insert("b", &m2)?;
-        for table in &lsh.hash_tables {
-            assert!(table.len() >= 1);
-            let table_values: HashSet<_> = table.values().flatten().collect();
-            assert!(table_values.contains(&"a"));
-            assert!(table_values.contains(&"b"));
+    /// The number of bands a candidate has to collide with the query in to best approximate
+    /// `threshold`, see `query_with_threshold`
+    pub fn min_matches_for(&self, threshold: f64) -> Result<usize> {
+        if !(0.0..=1.0).contains(&threshold) {
+            return Err(MinHashingError::WrongThresholdInterval);
+        }
+        let LshParams { b, r } = self.params;
+        Ok(LshParams::find_optimal_min_matches(
+            threshold,
+            b,
+            r,
+            &self.weights,
+        ))
+    }
+
+    /// Query for candidates that collide with the query in at least `min_matches` bands, e.g. as
+    /// computed once by `min_matches_for`
+    pub fn query_with_min_matches(
+        &self,
+        min_hash: &MinHash,
+        min_matches: usize,
+    ) -> Result<HashSet<KeyType>> {
+        if min_hash.hash_values.0.len() != self.num_perm {
+            return Err(MinHashingError::DifferentNumPermFuncs);
+        }
+        if self.empty_signature_policy == EmptySignaturePolicy::Bucket && min_hash.is_empty() {
+            return Ok(self.empty_keys.clone());
+        }
+        let mut matches: HashMap<&KeyType, usize> = HashMap::new();
+        for ((start, end), table) in self.hash_ranges.iter().zip(&self.hash_tables) {
+            let bucket = self
+                .band_hashing
+                .get(table, &min_hash.hash_values.0[*start..*end]);
+            for key in bucket.into_iter().flatten() {
+                *matches.entry(key).or_insert(0) += 1;
+            }
+        }
+        let candidates = matches
+            .into_iter()
+            .filter(|(_, num_matches)| *num_matches >= min_matches)
+            .map(|(key, _)| key.clone())
+            .collect();
+        Ok(candidates)
+    }
+
+    /// Query for the `k` most similar MinHash structs, ranked by the jaccard similarity estimated
+    /// from their stored signatures, with ties broken by key. If the bands yield fewer than `k`
+    /// candidates, up to 10,000 of the closest other buckets are probed as well, see
+    /// `query_top_k_with_budget`.
+    /// Requires signatures to be stored, see `with_signatures`.
+    pub fn query_top_k(&self, min_hash: &MinHash, k: usize) -> Result<Vec<(KeyType, f32)>>
+    where
+        KeyType: Ord,
+    {
+        self.query_top_k_with_budget(min_hash, k, _DEFAULT_PROBE_BUDGET)
+    }
+
+    /// Query for the `k` most similar MinHash structs like `query_top_k`, probing at most
+    /// `max_probed_buckets` buckets if the bands yield fewer than `k` candidates. The buckets
+    /// are ranked by the number of hash values their band differs from the query in, with ties
+    /// broken by their band values, and the keys of the closest ones are added until there are
+    /// `k` candidates. Ranking them takes a single pass over the hash tables. Fewer than `k`
+    /// MinHash structs are returned if the budget runs out, and as the jaccard similarity is only
+    /// estimated from the bands, the ones found are not necessarily the most similar ones in the
+    /// index. The result only depends on the contained MinHash structs, not on the order they
+    /// were inserted in. Bands stored as digests cannot be compared, so their buckets are not
+    /// probed.
+    pub fn query_top_k_with_budget(
+        &self,
+        min_hash: &MinHash,
+        k: usize,
+        max_probed_buckets: usize,
+    ) -> Result<Vec<(KeyType, f32)>>
+    where
+        KeyType: Ord,
+    {
+        let signatures = self
+            .signatures
+            .as_ref()
+            .ok_or(MinHashingError::SignaturesNotStored)?;
+        let mut candidates = self.query_candidates(min_hash)?;
+        if candidates.len() < k && self.band_hashing == BandHashing::Values {
+            self.probe_candidates(min_hash, k, max_probed_buckets, &mut candidates);
+        }
+
+        let mut ranked: Vec<(KeyType, f32)> = candidates
+            .into_iter()
+            .map(|candidate| {
+                let signature = stored_signature(signatures, &candidate)?;
+                let jaccard = estimate_jaccard(&min_hash.hash_values.0, &signature.0);
+                Ok((candidate, jaccard))
+            })
+            .collect::<Result<_>>()?;
+        ranked.sort_by(|(left_key, left), (right_key, right)| {
+            right
+                .partial_cmp(left)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| left_key.cmp(right_key))
+        });
+        ranked.truncate(k);
+        Ok(ranked)
+    }
+
+    /// Find all pairs of keys that share a bucket in at least one band, e.g. for deduplicating the
+    /// whole index without querying every MinHash struct. Buckets with more than
+    /// `max_bucket_size` keys are skipped, as they typically stem from degenerate signatures. If
+    /// `verify` is set, only pairs whose jaccard similarity estimated from their stored signatures
+    /// reaches the configured threshold are returned, which requires signatures to be stored. Each
+    /// pair is returned once with the smaller key first, and the pairs are sorted.
+    pub fn candidate_pairs(
+        &self,
+        max_bucket_size: Option<usize>,
+        verify: bool,
+    ) -> Result<Vec<(KeyType, KeyType)>>
+    where
+        KeyType: Ord,
+    {
+        let threshold = if verify { Some(self.threshold) } else { None };
+        let mut pairs: Vec<(KeyType, KeyType)> = self
+            .unique_candidate_pairs(max_bucket_size, threshold)?
+            .into_iter()
+            .map(|(left, right)| (left.clone(), right.clone()))
+            .collect();
+        pairs.sort_unstable();
+        Ok(pairs)
+    }
+
+    /// Group the keys into clusters of near-duplicates, i.e. the connected components of the
+    /// candidate pairs. Buckets with more than `max_bucket_size` keys are skipped like in
+    /// `candidate_pairs`. Without a `threshold`, all keys of a bucket are connected in time linear
+    /// in the bucket size. If a `threshold` is given, only keys whose jaccard similarity estimated
+    /// from their stored signatures reaches it are connected, which requires signatures to be
+    /// stored and compares the keys of a bucket pairwise, skipping keys already connected. Only
+    /// clusters with at least two keys are returned. The keys of a cluster are sorted, so that its
+    /// first key can serve as the representative, and the clusters are sorted by their
+    /// representative.
+    pub fn clusters(
+        &self,
+        max_bucket_size: Option<usize>,
+        threshold: Option<f64>,
+    ) -> Result<Vec<Vec<KeyType>>>
+    where
+        KeyType: Ord,
+    {
+        let signatures = match threshold {
+            Some(threshold) if !(0.0..=1.0).contains(&threshold) => {
+                return Err(MinHashingError::WrongThresholdInterval);
+            }
+            Some(threshold) => Some((
+                self.signatures
+                    .as_ref()
+                    .ok_or(MinHashingError::SignaturesNotStored)?,
+                threshold,
+            )),
+            None => None,
+        };
+        let mut indexes: HashMap<&KeyType, usize> = HashMap::new();
+        let mut keys = Vec::new();
+        let mut components = DisjointSets::default();
+        // The empty MinHash structs share a single bucket across all bands
+        let empty_bucket = match self.empty_signature_policy {
+            EmptySignaturePolicy::Bucket => Some(&self.empty_keys),
+            _ => None,
+        };
+        let buckets = self.hash_tables.iter().flat_map(|table| table.values());
+        for bucket in buckets.chain(empty_bucket) {
+            if bucket.len() < 2 || bucket.len() > max_bucket_size.unwrap_or(usize::MAX) {
+                continue;
+            }
+            let members: Vec<usize> = bucket
+                .iter()
+                .map(|key| {
+                    *indexes.entry(key).or_insert_with(|| {
+                        keys.push(key);
+                        components.add()
+                    })
+                })
+                .collect();
+            match signatures {
+                None => {
+                    for member in &members[1..] {
+                        components.union(members[0], *member);
+                    }
+                }
+                Some((signatures, threshold)) => {
+                    for (i, left) in members.iter().enumerate() {
+                        for right in &members[i + 1..] {
+                            if components.find(*left) == components.find(*right) {
+                                continue;
+                            }
+                            let jaccard = estimate_jaccard(
+                                &stored_signature(signatures, keys[*left])?.0,
+                                &stored_signature(signatures, keys[*right])?.0,
+                            );
+                            if jaccard as f64 >= threshold {
+                                components.union(*left, *right);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        let mut clusters: HashMap<usize, Vec<KeyType>> = HashMap::new();
+        for (index, key) in keys.into_iter().enumerate() {
+            clusters
+                .entry(components.find(index))
+                .or_default()
+                .push(key.clone());
+        }
+        let mut clusters: Vec<Vec<KeyType>> = clusters
+            .into_values()
+            .filter(|cluster| cluster.len() > 1)
+            .map(|mut cluster| {
+                cluster.sort_unstable();
+                cluster
+            })
+            .collect();
+        clusters.sort_unstable();
+        Ok(clusters)
+    }
+
+    /// Collect the unique pairs of keys sharing a bucket with at most `max_bucket_size` keys, with
+    /// the smaller key first. If a `threshold` is given, only pairs whose estimated jaccard
+    /// similarity reaches it are kept.
+    fn unique_candidate_pairs(
+        &self,
+        max_bucket_size: Option<usize>,
+        threshold: Option<f64>,
+    ) -> Result<HashSet<(&KeyType, &KeyType)>>
+    where
+        KeyType: Ord,
+    {
+        let signatures = match threshold {
+            Some(_) => Some(
+                self.signatures
+                    .as_ref()
+                    .ok_or(MinHashingError::SignaturesNotStored)?,
+            ),
+            None => None,
+        };
+        let mut pairs = HashSet::new();
+        // The empty MinHash structs share a single bucket across all bands
+        let empty_bucket = match self.empty_signature_policy {
+            EmptySignaturePolicy::Bucket => Some(&self.empty_keys),
+            _ => None,
+        };
+        let buckets = self.hash_tables.iter().flat_map(|table| table.values());
+        for bucket in buckets.chain(empty_bucket) {
+            if bucket.len() < 2 || bucket.len() > max_bucket_size.unwrap_or(usize::MAX) {
+                continue;
+            }
+            let mut keys: Vec<&KeyType> = bucket.iter().collect();
+            keys.sort_unstable();
+            for (i, left) in keys.iter().enumerate() {
+                for right in &keys[i + 1..] {
+                    pairs.insert((*left, *right));
+                }
+            }
+        }
+        if let (Some(signatures), Some(threshold)) = (signatures, threshold) {
+            let mut verified = HashSet::with_capacity(pairs.len());
+            for (left, right) in pairs {
+                let jaccard = estimate_jaccard(
+                    &stored_signature(signatures, left)?.0,
+                    &stored_signature(signatures, right)?.0,
+                );
+                if jaccard as f64 >= threshold {
+                    verified.insert((left, right));
+                }
+            }
+            pairs = verified;
+        }
+        Ok(pairs)
+    }
+
+    /// Rank the buckets whose band differs from the band of the query by the number of differing
+    /// hash values, then by their band values and band, and add the keys of up to `max_buckets`
+    /// of the closest ones until there are `k` candidates. The ranking does not depend on the
+    /// order of the hash tables, so equal indexes yield equal candidates.
+    fn probe_candidates(
+        &self,
+        min_hash: &MinHash,
+        k: usize,
+        max_buckets: usize,
+        candidates: &mut HashSet<KeyType>,
+    ) {
+        if max_buckets == 0 {
+            return;
+        }
+        let rank = |left: &ProbedBucket<'_, KeyType>, right: &ProbedBucket<'_, KeyType>| {
+            (left.0, left.1, left.2).cmp(&(right.0, right.1, right.2))
+        };
+        let mut closest: Vec<ProbedBucket<'_, KeyType>> = Vec::new();
+        // Buckets differing in more hash values than the kept ones cannot make it into the result
+        let mut max_mismatches = self.params.r;
+        for (band, ((start, end), table)) in
+            self.hash_ranges.iter().zip(&self.hash_tables).enumerate()
+        {
+            let query_part = &min_hash.hash_values.0[*start..*end];
+            for (part, keys) in table {
+                let part = part.as_slice();
+                let mismatches = part
+                    .iter()
+                    .zip(query_part)
+                    .filter(|(left, right)| left != right)
+                    .count();
+                // The bucket of the band itself holds candidates already
+                if mismatches == 0 || mismatches > max_mismatches {
+                    continue;
+                }
+                closest.push((mismatches, part, band, keys));
+                // Keep the memory bounded by dropping all but the closest buckets now and then
+                if closest.len() >= 2 * max_buckets {
+                    closest.select_nth_unstable_by(max_buckets - 1, rank);
+                    closest.truncate(max_buckets);
+                    max_mismatches = closest[max_buckets - 1].0;
+                }
+            }
+        }
+        closest.sort_unstable_by(rank);
+        for (_, _, _, keys) in closest.into_iter().take(max_buckets) {
+            if candidates.len() >= k {
+                break;
+            }
+            candidates.extend(keys.iter().cloned());
+        }
+    }
+
+    fn query_candidates(&self, min_hash: &MinHash) -> Result<HashSet<KeyType>> {
+        self.query_bands(min_hash, self.params.b)
+    }
+
+    /// Query for candidates that collide with the MinHash struct in one of the first `b` bands
+    pub(crate) fn query_bands(&self, min_hash: &MinHash, b: usize) -> Result<HashSet<KeyType>> {
+        let mut candidates = HashSet::new();
+        self.for_each_band_bucket(min_hash, b, |bucket| {
+            candidates.extend(bucket.iter().cloned())
+        })?;
+        Ok(candidates)
+    }
+
+    /// Call `f` with the bucket of each of the first `b` bands that the MinHash struct collides
+    /// with, without allocating
+    fn for_each_band_bucket<'a, F>(&'a self, min_hash: &MinHash, b: usize, mut f: F) -> Result<()>
+    where
+        F: FnMut(&'a HashSet<KeyType>),
+    {
+        if min_hash.hash_values.0.len() != self.num_perm {
+            return Err(MinHashingError::DifferentNumPermFuncs);
+        }
+        if self.empty_signature_policy == EmptySignaturePolicy::Bucket && min_hash.is_empty() {
+            f(&self.empty_keys);
+            return Ok(());
+        }
+        for ((start, end), table) in self.hash_ranges.iter().zip(&self.hash_tables).take(b) {
+            let hash_values = &min_hash.hash_values.0[*start..*end];
+            if let Some(bucket) = self.band_hashing.get(table, hash_values) {
+                f(bucket);
+            }
+        }
+        Ok(())
+    }
+}
+
+const _NUM_LARGEST_BUCKETS: usize = 10;
+
+// A bucket probed by `query_top_k` with the number of hash values its band differs from the
+// query in, its band values and its band
+type ProbedBucket<'a, KeyType> = (usize, &'a [u64], usize, &'a HashSet<KeyType>);
+
+/// Log a warning when a bucket has just grown beyond the maximum size
+fn warn_if_oversized(
+    max_bucket_size: Option<(usize, BucketOverflowPolicy)>,
+    band: usize,
+    bucket_size: usize,
+) {
+    if let Some((max_bucket_size, BucketOverflowPolicy::Warn)) = max_bucket_size {
+        if bucket_size == max_bucket_size + 1 {
+            log::warn!(
+                "A bucket in band {} exceeds the maximum size of {} keys",
+                band,
+                max_bucket_size
+            );
+        }
+    }
+}
+
+/// Look up the stored signature of a key, which is missing if the key was inserted before
+/// signatures were enabled
+fn stored_signature<'a, KeyType: Eq + Hash>(
+    signatures: &'a HashMap<KeyType, HashValues>,
+    key: &KeyType,
+) -> Result<&'a HashValues> {
+    signatures
+        .get(key)
+        .ok_or(MinHashingError::SignaturesNotStored)
+}
+
+/// Assert that every key is contained in exactly the buckets of its band parts, except for the
+/// `num_empty_keys` keys kept outside of the hash tables, and that all buckets are non-empty
+#[cfg(test)]
+pub(crate) fn assert_tables_consistent<'a, K: Eq + Hash + 'a>(
+    hash_tables: impl IntoIterator<Item = &'a HashMap<BandKey, HashSet<K>>>,
+    keys: &HashMap<K, Vec<BandKey>>,
+    num_empty_keys: usize,
+) {
+    for (band, table) in hash_tables.into_iter().enumerate() {
+        let num_memberships: usize = table.values().map(|bucket| bucket.len()).sum();
+        assert_eq!(num_memberships, keys.len() - num_empty_keys);
+        for (hash_part, bucket) in table.iter() {
+            assert!(!bucket.is_empty());
+            for key in bucket {
+                assert_eq!(&keys[key][band], hash_part);
+            }
+        }
+    }
+}
+
+/// Statistics about the hash tables of a MinHashLsh, see `MinHashLsh::stats`
+#[derive(Clone, Debug)]
+pub struct LshStats {
+    /// The number of contained MinHash structs
+    pub num_keys: usize,
+    /// The number of empty MinHash structs kept outside of the hash tables, see
+    /// `EmptySignaturePolicy`
+    pub num_empty_keys: usize,
+    /// The statistics of the hash table of each band
+    pub bands: Vec<BandStats>,
+    /// The number of buckets by size class, where the i-th entry counts the buckets holding
+    /// between 2^i and 2^(i+1) - 1 keys
+    pub bucket_size_histogram: Vec<usize>,
+    /// The largest buckets over all bands, largest first. Buckets holding a large share of the
+    /// keys typically stem from degenerate MinHash structs, e.g. of empty documents.
+    pub largest_buckets: Vec<LargeBucket>,
+    /// A rough estimate of the bytes used by the hash tables, key map and signatures, based on
+    /// their capacities. Memory owned by the keys themselves, e.g. the contents of strings, is
+    /// not included.
+    pub estimated_memory_bytes: usize,
+}
+
+/// Statistics about the hash table of a band
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BandStats {
+    /// The number of buckets
+    pub num_buckets: usize,
+    /// The number of keys over all buckets, i.e. the number of contained MinHash structs that
+    /// are not kept outside of the hash tables
+    pub num_keys: usize,
+    /// The number of keys in the largest bucket
+    pub max_bucket_size: usize,
+}
+
+/// A bucket reported by `LshStats::largest_buckets`
+#[derive(Clone, Debug)]
+pub struct LargeBucket {
+    /// The band of the bucket
+    pub band: usize,
+    /// The number of keys in the bucket
+    pub size: usize,
+    /// The band part shared by all keys in the bucket, i.e. their hash values or the words of
+    /// their digest, see `BandHashing`
+    pub hash_value_part: HashValuePart,
+}
+
+/// A union-find structure over the indexes `0..n`
+#[derive(Default)]
+struct DisjointSets {
+    parents: Vec<usize>,
+}
+
+impl DisjointSets {
+    fn add(&mut self) -> usize {
+        self.parents.push(self.parents.len());
+        self.parents.len() - 1
+    }
+
+    fn find(&mut self, mut index: usize) -> usize {
+        while self.parents[index] != index {
+            // Path halving
+            self.parents[index] = self.parents[self.parents[index]];
+            index = self.parents[index];
+        }
+        index
+    }
+
+    fn union(&mut self, left: usize, right: usize) {
+        let (left, right) = (self.find(left), self.find(right));
+        if left != right {
+            self.parents[left.max(right)] = left.min(right);
+        }
+    }
+}
+
+/// A session for bulk loading MinHash structs into a MinHashLsh, see
+/// `MinHashLsh::insertion_session`. The remaining buffered MinHash structs are applied when the
+/// session is dropped.
+pub struct InsertionSession<'a, KeyType: Eq + Hash + Clone> {
+    lsh: &'a mut MinHashLsh<KeyType>,
+    buffer: Vec<(KeyType, Vec<BandKey>, Option<HashValues>)>,
+    // The position of each buffered key in the buffer
+    buffered_keys: HashMap<KeyType, usize>,
+}
+
+impl<KeyType: Eq + Hash + Clone> InsertionSession<'_, KeyType> {
+    /// Buffer a new MinHash struct, applying the buffer once it holds `buffer_size` entries.
+    /// Duplicate keys are handled like in `MinHashLsh::insert`, including buffered ones.
+    pub fn insert(&mut self, key: KeyType, min_hash: &MinHash) -> Result<()> {
+        let hash_values = &min_hash.hash_values.0;
+        if hash_values.len() != self.lsh.num_perm {
+            return Err(MinHashingError::DifferentNumPermFuncs);
+        }
+        if self.lsh.max_bucket_size.is_some() || self.lsh.is_special_empty(hash_values) {
+            // Bucket sizes and empty MinHash structs are handled one key at a time
+            self.flush();
+            return self.lsh.insert(key, min_hash);
+        }
+        let buffered = self.buffered_keys.get(&key).copied();
+        if buffered.is_some() {
+            if !self.lsh.accepts_duplicate()? {
+                return Ok(());
+            }
+        } else if !self.lsh.check_insert(&key, hash_values)? {
+            return Ok(());
+        } else if self.lsh.contains_key(&key) {
+            self.lsh.remove(&key)?;
+        }
+        let hash_value_parts = self.lsh.hash_value_parts(hash_values);
+        let signature = self
+            .lsh
+            .signatures
+            .as_ref()
+            .map(|_| HashValues(hash_values.clone()));
+        match buffered {
+            // A replaced buffered MinHash struct is overwritten in place
+            Some(index) => self.buffer[index] = (key, hash_value_parts, signature),
+            None => {
+                self.buffered_keys.insert(key.clone(), self.buffer.len());
+                self.buffer.push((key, hash_value_parts, signature));
+            }
+        }
+        if self.buffer.len() >= self.lsh.buffer_size {
+            self.flush();
+        }
+        Ok(())
+    }
+
+    /// The number of buffered MinHash structs that are not applied yet
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Apply all buffered MinHash structs to the MinHashLsh
+    pub fn flush(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        let lsh = &mut *self.lsh;
+        let num_buffered = self.buffer.len();
+        // Every buffered MinHash struct adds at most one bucket per band
+        for table in &mut lsh.hash_tables {
+            table.reserve(num_buffered);
+        }
+        lsh.keys.reserve(num_buffered);
+        for (band, table) in lsh.hash_tables.iter_mut().enumerate() {
+            for (key, hash_value_parts, _) in &self.buffer {
+                table
+                    .entry(hash_value_parts[band].clone())
+                    .or_insert_with(HashSet::new)
+                    .insert(key.clone());
+            }
+        }
+        self.buffered_keys.clear();
+        for (key, hash_value_parts, signature) in self.buffer.drain(..) {
+            if let (Some(signatures), Some(signature)) = (&mut lsh.signatures, signature) {
+                signatures.insert(key.clone(), signature);
+            }
+            lsh.keys.insert(key, hash_value_parts);
+        }
+    }
+}
+
+impl<KeyType: Eq + Hash + Clone> Drop for InsertionSession<'_, KeyType> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::minhash::{min_hash_of, MinHash};
+    use itertools::Itertools;
+
+    #[test]
+    fn test_init() -> Result<()> {
+        let lsh = <MinHashLsh<&str>>::new(128, None, Some(0.8))?;
+        assert!(lsh.is_empty());
+        let LshParams { b: b1, r: r1 } = lsh.params;
+        let lsh = <MinHashLsh<&str>>::new(128, Some(Weights(0.2, 0.8)), Some(0.8))?;
+        let LshParams { b: b2, r: r2 } = lsh.params;
+        assert!(b1 < b2);
+        assert!(r1 > r2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_builder() -> Result<()> {
+        let lsh: MinHashLsh<&str> = MinHashLsh::builder().num_perm(128).bands(16, 8).build()?;
+        assert_eq!(lsh.params().b, 16);
+        assert_eq!(lsh.params().r, 8);
+        assert_eq!(lsh.hash_tables.len(), 16);
+        assert_eq!(lsh.hash_ranges.last(), Some(&(120, 128)));
+        assert!(lsh.signatures.is_none());
+
+        let lsh: MinHashLsh<&str> = MinHashLsh::builder()
+            .num_perm(128)
+            .threshold(0.8)
+            .weights(Weights(0.2, 0.8))
+            .with_signatures()
+            .build()?;
+        let expected = <MinHashLsh<&str>>::new(128, Some(Weights(0.2, 0.8)), Some(0.8))?;
+        assert_eq!(lsh.params().b, expected.params().b);
+        assert_eq!(lsh.params().r, expected.params().r);
+        assert_eq!(lsh.threshold(), 0.8);
+        assert!(lsh.signatures.is_some());
+        assert!(lsh.false_positive_rate() > 0.0 && lsh.false_positive_rate() < 0.8);
+        assert!(lsh.false_negative_rate() > 0.0 && lsh.false_negative_rate() < 0.2);
+
+        for (b, r) in [(0, 8), (16, 0), (17, 8)] {
+            let result = <MinHashLsh<&str>>::builder().bands(b, r).build();
+            assert!(matches!(result, Err(MinHashingError::WrongBandParams)));
+        }
+        let result = <MinHashLsh<&str>>::builder()
+            .weights(Weights(0.2, 0.7))
+            .build();
+        assert!(matches!(result, Err(MinHashingError::UnexpectedSumWeight)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_optimal_min_matches() {
+        let weights = Weights(0.5, 0.5);
+        let LshParams { b, r } = LshParams::find_optimal_params(0.3, 128, &weights);
+        assert_eq!(LshParams::find_optimal_min_matches(0.3, b, r, &weights), 1);
+        let stricter = LshParams::find_optimal_min_matches(0.8, b, r, &weights);
+        assert!(stricter > 1);
+        assert!(LshParams::find_optimal_min_matches(0.95, b, r, &weights) >= stricter);
+        // All bands have to collide
+        let all_bands = f64::powi(0.5, (b * r) as i32);
+        assert!((LshParams::collision_probability(0.5, b, r, b) - all_bands).abs() < 1e-9);
+        assert!(
+            LshParams::collision_probability(0.5, b, r, 2)
+                < LshParams::collision_probability(0.5, b, r, 1)
+        );
+    }
+
+    #[test]
+    fn test_insert() -> Result<()> {
+        let mut lsh = <MinHashLsh<&str>>::new(128, None, Some(0.5))?;
+        let mut m1 = <MinHash>::new(128, Some(0));
+        m1.update(&"a");
+        let mut m2 = <MinHash>::new(128, Some(0));
+        m2.update(&"b");
+        lsh.insert("a", &m1)?;
+        lsh.insert("b", &m2)?;
+        for table in &lsh.hash_tables {
+            assert!(!table.is_empty());
+            let table_values: HashSet<_> = table.values().flatten().collect();
+            assert!(table_values.contains(&"a"));
+            assert!(table_values.contains(&"b"));
         }
         assert!(lsh.contains_key(&"a"));
         assert!(lsh.contains_key(&"b"));
@@ -257,6 +1620,230 @@ mod test {
         Ok(())
     }
 
+    fn assert_buckets_consistent<K: Eq + Hash + Clone>(lsh: &MinHashLsh<K>) {
+        assert_tables_consistent(&lsh.hash_tables, &lsh.keys, lsh.empty_keys.len());
+    }
+
+    #[test]
+    fn test_duplicate_key_policy() -> Result<()> {
+        let mut m1 = <MinHash>::new(16, Some(1));
+        m1.update(&"a");
+        let mut m2 = <MinHash>::new(16, Some(1));
+        m2.update(&"b");
+
+        let mut lsh = <MinHashLsh<&str>>::new(16, None, Some(0.5))?;
+        assert_eq!(lsh.duplicate_key_policy(), DuplicateKeyPolicy::Replace);
+        lsh.insert("a", &m1)?;
+        lsh.insert("a", &m2)?;
+        assert_buckets_consistent(&lsh);
+        assert!(lsh.query(&m2)?.contains(&"a"));
+        assert!(!lsh.query(&m1)?.contains(&"a"));
+        lsh.remove(&"a")?;
+        assert!(lsh.hash_tables.iter().all(|table| table.is_empty()));
+
+        let mut lsh: MinHashLsh<&str> = MinHashLsh::builder()
+            .num_perm(16)
+            .threshold(0.5)
+            .duplicate_key_policy(DuplicateKeyPolicy::Error)
+            .build()?;
+        lsh.insert("a", &m1)?;
+        assert!(matches!(
+            lsh.insert("a", &m2),
+            Err(MinHashingError::KeyAlreadyExists)
+        ));
+        assert_buckets_consistent(&lsh);
+        assert!(lsh.query(&m1)?.contains(&"a"));
+
+        let mut lsh: MinHashLsh<&str> = MinHashLsh::builder()
+            .num_perm(16)
+            .threshold(0.5)
+            .duplicate_key_policy(DuplicateKeyPolicy::Ignore)
+            .build()?;
+        lsh.insert("a", &m1)?;
+        lsh.insert("a", &m2)?;
+        assert_buckets_consistent(&lsh);
+        assert!(lsh.query(&m1)?.contains(&"a"));
+        assert!(!lsh.query(&m2)?.contains(&"a"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_signature_policy() -> Result<()> {
+        let empty = <MinHash>::new(16, Some(1));
+        let mut m1 = <MinHash>::new(16, Some(1));
+        m1.update(&"a");
+        let build = |policy| {
+            MinHashLsh::<&str>::builder()
+                .num_perm(16)
+                .threshold(0.5)
+                .empty_signature_policy(policy)
+                .build()
+        };
+
+        let mut lsh = build(EmptySignaturePolicy::Allow)?;
+        lsh.insert("e1", &empty)?;
+        lsh.insert("e2", &empty)?;
+        assert_eq!(lsh.query(&empty)?.len(), 2);
+
+        let mut lsh = build(EmptySignaturePolicy::Reject)?;
+        assert!(matches!(
+            lsh.insert("e1", &empty),
+            Err(MinHashingError::EmptySignature)
+        ));
+        assert!(lsh.is_empty());
+
+        let mut lsh = build(EmptySignaturePolicy::Isolate)?;
+        lsh.insert("e1", &empty)?;
+        lsh.insert("e2", &empty)?;
+        lsh.insert("a", &m1)?;
+        assert_eq!(lsh.len(), 3);
+        assert!(lsh.query(&empty)?.is_empty());
+        assert!(lsh.query_by_key(&"e1", false)?.is_empty());
+        assert!(lsh.candidate_pairs(None, false)?.is_empty());
+        assert_buckets_consistent(&lsh);
+        lsh.remove(&"e1")?;
+        assert!(!lsh.contains_key(&"e1"));
+
+        let mut lsh = build(EmptySignaturePolicy::Bucket)?;
+        lsh.insert("e1", &empty)?;
+        lsh.insert("e2", &empty)?;
+        lsh.insert("a", &m1)?;
+        assert_buckets_consistent(&lsh);
+        let expected: HashSet<&str> = vec!["e1", "e2"].into_iter().collect();
+        assert_eq!(lsh.query(&empty)?, expected);
+        assert_eq!(lsh.query_with_threshold(&empty, 0.9)?, expected);
+        assert_eq!(
+            lsh.query_by_key(&"e1", true)?,
+            vec!["e2"].into_iter().collect()
+        );
+        assert!(!lsh.query(&m1)?.contains("e1"));
+        assert_eq!(lsh.candidate_pairs(None, false)?, vec![("e1", "e2")]);
+        assert_eq!(lsh.stats().num_empty_keys, 2);
+
+        // Updating moves keys in and out of the bucket of empty MinHash structs
+        assert_eq!(lsh.upsert("e1", &m1)?, lsh.params.b);
+        assert!(lsh.query(&m1)?.contains("e1"));
+        assert_eq!(lsh.upsert("a", &empty)?, lsh.params.b);
+        assert_eq!(lsh.query(&empty)?, vec!["a", "e2"].into_iter().collect());
+        assert_buckets_consistent(&lsh);
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_bucket_size() -> Result<()> {
+        let mut lsh: MinHashLsh<u32> = MinHashLsh::builder()
+            .num_perm(16)
+            .threshold(0.5)
+            .max_bucket_size(2, BucketOverflowPolicy::Error)
+            .build()?;
+        let mut m1 = <MinHash>::new(16, Some(1));
+        m1.update(&"a");
+        lsh.insert(1, &m1)?;
+        lsh.insert(2, &m1)?;
+        // Reinserting a contained key does not grow its buckets
+        lsh.insert(2, &m1)?;
+        assert!(matches!(
+            lsh.insert(3, &m1),
+            Err(MinHashingError::BucketSizeExceeded)
+        ));
+        {
+            let mut session = lsh.insertion_session();
+            assert!(matches!(
+                session.insert(3, &m1),
+                Err(MinHashingError::BucketSizeExceeded)
+            ));
+        }
+        let mut m2 = <MinHash>::new(16, Some(1));
+        m2.update(&"b");
+        lsh.insert(3, &m2)?;
+        assert!(matches!(
+            lsh.upsert(3, &m1),
+            Err(MinHashingError::BucketSizeExceeded)
+        ));
+        assert_eq!(lsh.len(), 3);
+        assert_buckets_consistent(&lsh);
+
+        let mut lsh: MinHashLsh<u32> = MinHashLsh::builder()
+            .num_perm(16)
+            .threshold(0.5)
+            .max_bucket_size(2, BucketOverflowPolicy::Warn)
+            .build()?;
+        for key in 0..5 {
+            lsh.insert(key, &m1)?;
+        }
+        assert_eq!(lsh.stats().bands[0].max_bucket_size, 5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_insertion_session_duplicate_keys() -> Result<()> {
+        let mut m1 = <MinHash>::new(16, Some(1));
+        m1.update(&"a");
+        let mut m2 = <MinHash>::new(16, Some(1));
+        m2.update(&"b");
+
+        let mut lsh: MinHashLsh<&str> = MinHashLsh::builder()
+            .num_perm(16)
+            .threshold(0.5)
+            .buffer_size(8)
+            .build()?;
+        lsh.insert("a", &m1)?;
+        {
+            let mut session = lsh.insertion_session();
+            // Replaces both the contained and the buffered MinHash struct
+            session.insert("a", &m1)?;
+            session.insert("a", &m2)?;
+            session.insert("b", &m1)?;
+            assert_eq!(session.buffered(), 2);
+        }
+        assert_buckets_consistent(&lsh);
+        assert_eq!(lsh.keys.len(), 2);
+        assert!(lsh.query(&m2)?.contains(&"a"));
+        assert!(!lsh.query(&m1)?.contains(&"a"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_insertion_session() -> Result<()> {
+        let mut lsh: MinHashLsh<u32> = MinHashLsh::builder()
+            .num_perm(16)
+            .threshold(0.5)
+            .buffer_size(4)
+            .with_signatures()
+            .build()?;
+        let min_hashes: Vec<MinHash> = (0..10)
+            .map(|i| {
+                let mut min_hash = <MinHash>::new(16, Some(1));
+                min_hash.update(&format!("item-{}", i));
+                min_hash
+            })
+            .collect();
+        {
+            let mut session = lsh.insertion_session();
+            for (i, min_hash) in min_hashes.iter().enumerate() {
+                session.insert(i as u32, min_hash)?;
+            }
+            // Two full buffers were applied, the remaining two entries are applied on drop
+            assert_eq!(session.buffered(), 2);
+            assert!(matches!(
+                session.insert(10, &<MinHash>::new(8, Some(1))),
+                Err(MinHashingError::DifferentNumPermFuncs)
+            ));
+        }
+
+        let mut expected = <MinHashLsh<u32>>::new(16, None, Some(0.5))?;
+        for (i, min_hash) in min_hashes.iter().enumerate() {
+            expected.insert(i as u32, min_hash)?;
+        }
+        assert_eq!(lsh.keys, expected.keys);
+        assert_eq!(lsh.hash_tables, expected.hash_tables);
+        assert_eq!(lsh.signatures.as_ref().map(|s| s.len()), Some(10));
+        for (i, min_hash) in min_hashes.iter().enumerate() {
+            assert!(lsh.query(min_hash)?.contains(&(i as u32)));
+        }
+        Ok(())
+    }
+
     #[test]
     fn test_query() -> Result<()> {
         let mut lsh = <MinHashLsh<&str>>::new(16, None, Some(0.5))?;
@@ -280,6 +1867,446 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_query_into() -> Result<()> {
+        for band_hashing in [
+            BandHashing::Values,
+            BandHashing::Digest64,
+            BandHashing::Digest128,
+        ] {
+            let mut lsh: MinHashLsh<u32> = MinHashLsh::builder()
+                .num_perm(32)
+                .threshold(0.5)
+                .band_hashing(band_hashing)
+                .build()?;
+            for i in 0..20 {
+                lsh.insert(i, &min_hash_of(32, i * 5..i * 5 + 30))?;
+            }
+            let mut candidates = HashSet::new();
+            candidates.insert(100);
+            for i in 0..20 {
+                let query = min_hash_of(32, i * 5..i * 5 + 30);
+                let expected = lsh.query(&query)?;
+                lsh.query_into(&query, &mut candidates)?;
+                assert_eq!(candidates, expected);
+
+                let mut visited = Vec::new();
+                lsh.for_each_candidate(&query, |key| visited.push(*key))?;
+                assert!(visited.len() >= expected.len());
+                assert_eq!(visited.into_iter().collect::<HashSet<u32>>(), expected);
+            }
+            assert!(matches!(
+                lsh.query_into(&<MinHash>::new(16, Some(1)), &mut candidates),
+                Err(MinHashingError::DifferentNumPermFuncs)
+            ));
+        }
+
+        let part = BandKey::Values(vec![1, 2, 3]);
+        let mut table = HashMap::new();
+        table.insert(part.clone(), 1);
+        assert_eq!(table.get(&[1, 2, 3][..]), Some(&1));
+        assert_eq!(BandKey::Digest64([7]).as_slice(), &[7]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_by_key() -> Result<()> {
+        let mut lsh = <MinHashLsh<&str>>::new(16, None, Some(0.5))?;
+        let mut m1 = <MinHash>::new(16, Some(1));
+        m1.update(&"a");
+        m1.update(&"b");
+        let mut m2 = m1.clone();
+        m2.update(&"c");
+        let mut m3 = <MinHash>::new(16, Some(1));
+        m3.update(&"x");
+        lsh.insert("m1", &m1)?;
+        lsh.insert("m2", &m2)?;
+        lsh.insert("m3", &m3)?;
+
+        for (key, min_hash) in [("m1", &m1), ("m2", &m2), ("m3", &m3)] {
+            assert_eq!(lsh.query_by_key(&key, false)?, lsh.query(min_hash)?);
+        }
+        let result = lsh.query_by_key(&"m1", true)?;
+        assert!(result.contains("m2"));
+        assert!(!result.contains("m1"));
+        assert!(!result.contains("m3"));
+        assert!(matches!(
+            lsh.query_by_key(&"m4", false),
+            Err(MinHashingError::KeyDoesNotExist)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_candidate_pairs() -> Result<()> {
+        let mut lsh: MinHashLsh<u32> = MinHashLsh::builder()
+            .num_perm(32)
+            .threshold(0.5)
+            .with_signatures()
+            .build()?;
+        lsh.insert(3, &min_hash_of(32, 0..100))?;
+        lsh.insert(1, &min_hash_of(32, 0..100))?;
+        lsh.insert(2, &min_hash_of(32, 5..100))?;
+        lsh.insert(4, &min_hash_of(32, 1000..1100))?;
+
+        let pairs = lsh.candidate_pairs(None, false)?;
+        assert_eq!(pairs, vec![(1, 2), (1, 3), (2, 3)]);
+        let mut expected = Vec::new();
+        for key in 1..=4 {
+            for other in lsh.query_by_key(&key, true)? {
+                if key < other {
+                    expected.push((key, other));
+                }
+            }
+        }
+        expected.sort_unstable();
+        assert_eq!(pairs, expected);
+        assert_eq!(lsh.candidate_pairs(None, true)?, pairs);
+        // 1 and 3 are identical, so 2 only shares buckets with both of them at once
+        assert_eq!(lsh.candidate_pairs(Some(2), false)?, vec![(1, 3)]);
+        assert!(lsh.candidate_pairs(Some(1), false)?.is_empty());
+
+        lsh.insert(5, &<MinHash>::new(32, Some(1)))?;
+        lsh.insert(6, &<MinHash>::new(32, Some(1)))?;
+        // Empty MinHash structs collide in every band, but also match in the verification
+        assert!(lsh.candidate_pairs(None, true)?.contains(&(5, 6)));
+
+        let lsh = <MinHashLsh<u32>>::new(32, None, Some(0.5))?;
+        assert!(matches!(
+            lsh.candidate_pairs(None, true),
+            Err(MinHashingError::SignaturesNotStored)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_clusters() -> Result<()> {
+        let mut lsh: MinHashLsh<u32> = MinHashLsh::builder()
+            .num_perm(64)
+            .threshold(0.7)
+            .with_signatures()
+            .build()?;
+        // Two chains of near-duplicates and a singleton
+        lsh.insert(7, &min_hash_of(64, 0..100))?;
+        lsh.insert(3, &min_hash_of(64, 2..102))?;
+        lsh.insert(5, &min_hash_of(64, 4..104))?;
+        lsh.insert(2, &min_hash_of(64, 1000..1100))?;
+        lsh.insert(9, &min_hash_of(64, 1001..1101))?;
+        lsh.insert(1, &min_hash_of(64, 5000..5100))?;
+
+        let clusters = lsh.clusters(None, None)?;
+        assert_eq!(clusters, vec![vec![2, 9], vec![3, 5, 7]]);
+        assert_eq!(lsh.clusters(None, Some(0.5))?, clusters);
+        assert!(matches!(
+            lsh.clusters(None, Some(1.5)),
+            Err(MinHashingError::WrongThresholdInterval)
+        ));
+
+        let mut components = DisjointSets::default();
+        let indexes: Vec<usize> = (0..5).map(|_| components.add()).collect();
+        components.union(indexes[3], indexes[4]);
+        components.union(indexes[4], indexes[1]);
+        assert_eq!(components.find(3), 1);
+        assert_eq!(components.find(4), 1);
+        assert_eq!(components.find(2), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_clusters_large_bucket() -> Result<()> {
+        let mut lsh: MinHashLsh<u32> = MinHashLsh::builder()
+            .num_perm(32)
+            .threshold(0.5)
+            .with_signatures()
+            .build()?;
+        // Empty MinHash structs collide in every band, which makes one large bucket per band
+        for key in 0..3000 {
+            lsh.insert(key, &<MinHash>::new(32, Some(0)))?;
+        }
+        lsh.insert(3000, &min_hash_of(32, 0..100))?;
+        lsh.insert(3001, &min_hash_of(32, 0..100))?;
+
+        let expected: Vec<Vec<u32>> = vec![(0..3000).collect(), vec![3000, 3001]];
+        assert_eq!(lsh.clusters(None, None)?, expected);
+        assert_eq!(lsh.clusters(None, Some(0.9))?, expected);
+        assert_eq!(lsh.clusters(Some(2), None)?, vec![vec![3000, 3001]]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_band_hashing() -> Result<()> {
+        let min_hashes: Vec<MinHash> = (0..200)
+            .map(|i| min_hash_of(64, i * 3..i * 3 + 40))
+            .collect();
+        let mut expected = <MinHashLsh<u32>>::new(64, None, Some(0.5))?;
+        for (i, min_hash) in min_hashes.iter().enumerate() {
+            expected.insert(i as u32, min_hash)?;
+        }
+        for band_hashing in [BandHashing::Digest64, BandHashing::Digest128] {
+            let mut lsh: MinHashLsh<u32> = MinHashLsh::builder()
+                .num_perm(64)
+                .threshold(0.5)
+                .band_hashing(band_hashing)
+                .with_signatures()
+                .build()?;
+            for (i, min_hash) in min_hashes.iter().enumerate() {
+                lsh.insert(i as u32, min_hash)?;
+            }
+            // The same buckets, just with digests instead of hash values
+            for (table, expected_table) in lsh.hash_tables.iter().zip(&expected.hash_tables) {
+                let mut buckets: Vec<Vec<u32>> = table
+                    .values()
+                    .map(|keys| keys.iter().cloned().sorted().collect())
+                    .collect();
+                let mut expected_buckets: Vec<Vec<u32>> = expected_table
+                    .values()
+                    .map(|keys| keys.iter().cloned().sorted().collect())
+                    .collect();
+                buckets.sort();
+                expected_buckets.sort();
+                assert_eq!(buckets, expected_buckets);
+            }
+            for min_hash in &min_hashes {
+                assert_eq!(lsh.query(min_hash)?, expected.query(min_hash)?);
+            }
+            // The buckets of digests are not probed
+            assert!(lsh.query_top_k(&min_hash_of(64, 5000..5040), 3)?.is_empty());
+        }
+
+        let values = [1, 2, 3, 4];
+        let digests: HashSet<BandKey> = vec![
+            BandHashing::Digest64.band_key(&values),
+            BandHashing::Digest64.band_key(&[1, 2, 4, 3]),
+            BandHashing::Digest64.band_key(&[1, 2, 3, 5]),
+            BandHashing::Digest64.band_key(&[0, 2, 3, 4]),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(digests.len(), 4);
+        assert_eq!(
+            BandHashing::Values.band_key(&values),
+            BandKey::Values(values.to_vec())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_verified() -> Result<()> {
+        let lsh = <MinHashLsh<&str>>::new(16, None, Some(0.5))?;
+        let m1 = <MinHash>::new(16, Some(0));
+        assert!(matches!(
+            lsh.query_verified(&m1),
+            Err(MinHashingError::SignaturesNotStored)
+        ));
+
+        let mut lsh = lsh.with_signatures();
+        let mut m1 = <MinHash>::new(16, Some(0));
+        let mut m2 = <MinHash>::new(16, Some(0));
+        for value in 0..100 {
+            m1.update(&value);
+            m2.update(&(value + 90));
+        }
+        // Make the first band collide so that m2 is a false positive candidate of m1
+        m2.hash_values.0[..lsh.params.r].copy_from_slice(&m1.hash_values.0[..lsh.params.r]);
+        lsh.insert("m1", &m1)?;
+        lsh.insert("m2", &m2)?;
+
+        assert!(lsh.query(&m1)?.contains(&"m2"));
+        let verified = lsh.query_verified(&m1)?;
+        assert_eq!(verified, vec![("m1", 1.0)]);
+
+        lsh.remove(&"m1")?;
+        assert!(lsh.query_verified(&m1)?.is_empty());
+        assert!(!lsh.signatures.as_ref().unwrap().contains_key(&"m1"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_signatures_enabled_late() -> Result<()> {
+        let mut lsh = <MinHashLsh<&str>>::new(16, None, Some(0.5))?;
+        let mut m1 = <MinHash>::new(16, Some(0));
+        for value in 0..100 {
+            m1.update(&value);
+        }
+        lsh.insert("m1", &m1)?;
+        let mut lsh = lsh.with_signatures();
+        lsh.insert("m2", &m1)?;
+
+        // The signature of m1 is missing, so it cannot be verified
+        assert!(matches!(
+            lsh.query_verified(&m1),
+            Err(MinHashingError::SignaturesNotStored)
+        ));
+        assert!(matches!(
+            lsh.query_top_k(&m1, 2),
+            Err(MinHashingError::SignaturesNotStored)
+        ));
+        assert!(matches!(
+            lsh.candidate_pairs(None, true),
+            Err(MinHashingError::SignaturesNotStored)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_with_threshold() -> Result<()> {
+        let mut lsh = <MinHashLsh<&str>>::new(128, None, Some(0.2))?;
+        let mut query = <MinHash>::new(128, Some(0));
+        let mut similar = <MinHash>::new(128, Some(0));
+        let mut related = <MinHash>::new(128, Some(0));
+        for value in 0..100 {
+            query.update(&value);
+            similar.update(&value);
+            related.update(&(value + 50));
+        }
+        lsh.insert("similar", &similar)?;
+        lsh.insert("related", &related)?;
+
+        let result = lsh.query_with_threshold(&query, 0.2)?;
+        assert!(result.contains("similar"));
+        assert!(result.contains("related"));
+        let result = lsh.query_with_threshold(&query, 0.9)?;
+        assert!(result.contains("similar"));
+        assert!(!result.contains("related"));
+        assert!(matches!(
+            lsh.query_with_threshold(&query, 1.5),
+            Err(MinHashingError::WrongThresholdInterval)
+        ));
+
+        let min_matches = lsh.min_matches_for(0.9)?;
+        assert!(min_matches > 1);
+        assert_eq!(lsh.query_with_min_matches(&query, min_matches)?, result);
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_top_k() -> Result<()> {
+        let mut lsh = <MinHashLsh<u32>>::new(64, None, Some(0.8))?.with_signatures();
+        let mut query = <MinHash>::new(64, Some(0));
+        for value in 0..100 {
+            query.update(&value);
+        }
+        // Documents sharing 100 - 10 * i values with the query
+        for i in 0..10 {
+            let mut min_hash = <MinHash>::new(64, Some(0));
+            for value in (10 * i)..(100 + 10 * i) {
+                min_hash.update(&value);
+            }
+            lsh.insert(i, &min_hash)?;
+        }
+        lsh.insert(10, &query)?;
+
+        let top_k = lsh.query_top_k(&query, 3)?;
+        assert_eq!(top_k.len(), 3);
+        assert_eq!(top_k[0], (0, 1.0));
+        assert_eq!(top_k[1], (10, 1.0));
+        assert!(top_k[2].1 < 1.0);
+        assert!(top_k.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+
+        // Fewer candidates than k in the bands, so the buckets are probed
+        let top_k = lsh.query_top_k(&query, 11)?;
+        assert_eq!(top_k.len(), 11);
+        assert_eq!(top_k.last().unwrap().0, 9);
+        // Without a budget, only the candidates of the bands are ranked
+        let num_candidates = lsh.query(&query)?.len();
+        assert!(num_candidates < 11);
+        let top_k = lsh.query_top_k_with_budget(&query, 11, 0)?;
+        assert_eq!(top_k.len(), num_candidates);
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_top_k_deterministic() -> Result<()> {
+        let min_hashes: Vec<MinHash> = (0..2000)
+            .map(|i| min_hash_of(64, i * 7..i * 7 + 60))
+            .collect();
+        let query = min_hash_of(64, 100_000..100_060);
+        let mut results = Vec::new();
+        // Every hash table has its own random order of buckets
+        for _ in 0..5 {
+            let mut lsh = <MinHashLsh<u32>>::new(64, None, Some(0.8))?.with_signatures();
+            for (i, min_hash) in min_hashes.iter().enumerate() {
+                lsh.insert(i as u32, min_hash)?;
+            }
+            assert!(lsh.query(&query)?.len() < 5);
+            results.push(lsh.query_top_k_with_budget(&query, 5, 200)?);
+        }
+        assert_eq!(results[0].len(), 5);
+        assert!(results.iter().all(|result| *result == results[0]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_upsert() -> Result<()> {
+        let mut lsh: MinHashLsh<&str> = MinHashLsh::builder()
+            .num_perm(16)
+            .bands(4, 4)
+            .with_signatures()
+            .build()?;
+        let mut m1 = <MinHash>::new(16, Some(1));
+        m1.update(&"a");
+        assert_eq!(lsh.upsert("a", &m1)?, 4);
+        assert_eq!(lsh.upsert("a", &m1)?, 0);
+
+        // Change the hash values of the second and fourth band only
+        let mut m2 = m1.clone();
+        m2.hash_values.0[5] += 1;
+        m2.hash_values.0[6] += 1;
+        m2.hash_values.0[15] += 1;
+        assert_eq!(lsh.upsert("a", &m2)?, 2);
+        assert_buckets_consistent(&lsh);
+        assert_eq!(lsh.keys.len(), 1);
+        assert_eq!(lsh.signatures.as_ref().unwrap()["a"].0, m2.hash_values.0);
+        assert!(lsh.hash_tables.iter().all(|table| table.len() == 1));
+
+        let mut expected = lsh.clone();
+        expected.remove(&"a")?;
+        expected.insert("a", &m2)?;
+        assert_eq!(lsh.keys, expected.keys);
+        assert_eq!(lsh.hash_tables, expected.hash_tables);
+
+        assert!(matches!(
+            lsh.upsert("a", &<MinHash>::new(8, Some(1))),
+            Err(MinHashingError::DifferentNumPermFuncs)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_len_and_iter() -> Result<()> {
+        let mut lsh = <MinHashLsh<&str>>::new(16, None, Some(0.5))?;
+        assert!(lsh.is_empty());
+        assert_eq!(lsh.len(), 0);
+        let mut m1 = <MinHash>::new(16, Some(0));
+        m1.update(&"a");
+        let mut m2 = <MinHash>::new(16, Some(0));
+        m2.update(&"b");
+        lsh.insert("a", &m1)?;
+        assert!(!lsh.is_empty());
+        lsh.insert("b", &m2)?;
+        lsh.insert("b", &m2)?;
+        assert_eq!(lsh.len(), 2);
+
+        let entries: HashMap<&str, Vec<&[u64]>> = lsh
+            .iter()
+            .map(|(key, parts)| (*key, parts.collect()))
+            .collect();
+        assert_eq!(entries.len(), 2);
+        for (key, min_hash) in [("a", &m1), ("b", &m2)] {
+            let parts = &entries[key];
+            assert_eq!(parts.len(), lsh.params.b);
+            for (part, (start, end)) in parts.iter().zip(&lsh.hash_ranges) {
+                assert_eq!(*part, &min_hash.hash_values.0[*start..*end]);
+            }
+        }
+
+        lsh.remove(&"a")?;
+        lsh.remove(&"b")?;
+        assert!(lsh.is_empty());
+        assert_eq!(lsh.iter().count(), 0);
+        Ok(())
+    }
+
     #[test]
     fn test_remove() -> Result<()> {
         let mut lsh = <MinHashLsh<&str>>::new(16, None, Some(0.5))?;
@@ -294,7 +2321,7 @@ mod test {
         assert!(!lsh.keys.contains_key("&a"));
         for table in lsh.hash_tables {
             for value in table.keys() {
-                assert!(table[value].len() > 0);
+                assert!(!table[value].is_empty());
                 assert!(!table[value].contains(&"a"))
             }
         }
@@ -316,6 +2343,59 @@ mod test {
         for table in &counts {
             assert_eq!(table.values().sum::<usize>(), 2);
         }
+        let (start, end) = lsh.hash_ranges[0];
+        let part = HashValuePart(m1.hash_values.0[start..end].to_vec());
+        assert!(counts[0][&part] >= 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_stats() -> Result<()> {
+        let mut lsh: MinHashLsh<u32> = MinHashLsh::builder()
+            .num_perm(16)
+            .bands(4, 4)
+            .with_signatures()
+            .build()?;
+        let stats = lsh.stats();
+        assert_eq!(stats.num_keys, 0);
+        assert_eq!(stats.bands, vec![BandStats::default(); 4]);
+        assert!(stats.bucket_size_histogram.is_empty());
+        assert!(stats.largest_buckets.is_empty());
+
+        // Empty MinHash structs all end up in the same bucket of every band
+        for key in 0..20 {
+            lsh.insert(key, &<MinHash>::new(16, Some(1)))?;
+        }
+        for key in 20..25 {
+            let mut min_hash = <MinHash>::new(16, Some(1));
+            min_hash.update(&key);
+            lsh.insert(key, &min_hash)?;
+        }
+        let empty_stats = lsh.stats();
+        assert_eq!(empty_stats.num_keys, 25);
+        for band in &empty_stats.bands {
+            assert_eq!(band.num_keys, 25);
+            assert_eq!(band.num_buckets, 6);
+            assert_eq!(band.max_bucket_size, 20);
+        }
+        // 20 buckets of size 1 and four buckets of size 20
+        assert_eq!(empty_stats.bucket_size_histogram, vec![20, 0, 0, 0, 4]);
+        assert_eq!(empty_stats.largest_buckets.len(), 10);
+        let largest: Vec<(usize, usize)> = empty_stats.largest_buckets[..4]
+            .iter()
+            .map(|bucket| (bucket.band, bucket.size))
+            .sorted()
+            .collect();
+        assert_eq!(largest, vec![(0, 20), (1, 20), (2, 20), (3, 20)]);
+        assert_eq!(
+            empty_stats.largest_buckets[0].hash_value_part.0.as_slice(),
+            &[(1 << 32) - 1; 4]
+        );
+        assert!(empty_stats.largest_buckets[4..]
+            .iter()
+            .all(|bucket| bucket.size == 1));
+        assert!(empty_stats.estimated_memory_bytes > stats.estimated_memory_bytes);
+        assert!(empty_stats.estimated_memory_bytes > 25 * 16 * size_of::<u64>());
         Ok(())
     }
 
@@ -388,8 +2468,8 @@ mod test {
 
         // Create LSHindex
         let mut lsh = <MinHashLsh<&str>>::new(128, None, Some(0.5))?;
-        lsh.insert(&"m2", &m2)?;
-        lsh.insert(&"m3", &m3)?;
+        lsh.insert("m2", &m2)?;
+        lsh.insert("m3", &m3)?;
         let result = lsh.query(&m1);
         println!(
             "Approximate neighbours with Jaccard similarity > 0.5: {:?}",
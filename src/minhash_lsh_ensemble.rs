@@ -0,0 +1,331 @@
+use crate::error::MinHashingError;
+use crate::minhash::MinHash;
+use crate::minhash_lsh::{
+    validate_weights, LshParams, MinHashLsh, Weights, _ALLOWED_INTEGRATE_ERR,
+};
+use quadrature::integrate;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::sync::Mutex;
+
+type Result<T> = std::result::Result<T, MinHashingError>;
+
+// The number of cached params after which the cache is cleared
+const _MAX_CACHED_PARAMS: usize = 4096;
+
+/// The MinHashLshEnsemble struct for containment search, as described in "LSH Ensemble:
+/// Internet-Scale Domain Search" by Zhu et al. The indexed sets are partitioned by their size, and
+/// each partition holds one MinHashLsh per band size `r` in `1..=max_r`, so that the number of
+/// bands and the band size can be tuned for every query and partition.
+#[derive(Clone)]
+pub struct MinHashLshEnsemble<KeyType: Eq + Hash + Clone> {
+    num_perm: usize,
+    num_part: usize,
+    max_r: usize,
+    weights: Weights,
+    partitions: Vec<Partition<KeyType>>,
+    params_cache: ParamsCache,
+}
+
+// The optimal params by containment threshold and `xq`, as finding them integrates the error
+// probabilities for every combination of b and r
+#[derive(Default)]
+struct ParamsCache(Mutex<HashMap<(u64, u64), LshParams>>);
+
+impl Clone for ParamsCache {
+    fn clone(&self) -> Self {
+        let cache = self.0.lock().unwrap_or_else(|err| err.into_inner());
+        ParamsCache(Mutex::new(cache.clone()))
+    }
+}
+
+#[derive(Clone)]
+struct Partition<KeyType: Eq + Hash + Clone> {
+    lower: usize,
+    upper: usize,
+    // The MinHashLsh with band size r is at position r - 1
+    indexes: Vec<MinHashLsh<KeyType>>,
+}
+
+impl<KeyType: Eq + Hash + Clone> MinHashLshEnsemble<KeyType> {
+    /// Build a new MinHashLshEnsemble struct with `num_part` size partitions (16 by default) and
+    /// band sizes of up to `max_r` (8 by default). Both must be positive, and `max_r` must not
+    /// exceed `num_perm`.
+    pub fn new(
+        num_perm: usize,
+        num_part: Option<usize>,
+        max_r: Option<usize>,
+        weights: Option<Weights>,
+    ) -> Result<MinHashLshEnsemble<KeyType>> {
+        let num_part = num_part.unwrap_or(16);
+        let max_r = max_r.unwrap_or(8);
+        if num_perm < 2 {
+            return Err(MinHashingError::NumPermFuncsTooLow);
+        }
+        if num_part == 0 || max_r == 0 || num_perm < max_r {
+            return Err(MinHashingError::WrongBandParams);
+        }
+        let weights = validate_weights(weights.unwrap_or(Weights(0.5, 0.5)))?;
+        Ok(MinHashLshEnsemble {
+            num_perm,
+            num_part,
+            max_r,
+            weights,
+            partitions: Vec::new(),
+            params_cache: ParamsCache::default(),
+        })
+    }
+
+    /// Check whether the MinHashLshEnsemble contains any MinHash structs
+    pub fn is_empty(&self) -> bool {
+        self.partitions.is_empty()
+    }
+
+    /// Index `(key, MinHash, set size)` entries, replacing any previously indexed ones. The
+    /// entries are split into partitions holding roughly the same number of sets each.
+    pub fn index(&mut self, mut entries: Vec<(KeyType, MinHash, usize)>) -> Result<()> {
+        if entries
+            .iter()
+            .any(|(_, min_hash, _)| min_hash.hash_values.0.len() != self.num_perm)
+        {
+            return Err(MinHashingError::DifferentNumPermFuncs);
+        }
+        entries.sort_by_key(|(_, _, size)| *size);
+
+        self.partitions = Vec::new();
+        let partition_len = entries.len().div_ceil(self.num_part);
+        let mut entries = entries.into_iter().peekable();
+        while entries.peek().is_some() {
+            let mut partition = self.new_partition();
+            let mut num_entries = 0;
+            // Sets of the same size always end up in the same partition
+            while let Some((key, min_hash, size)) = entries
+                .next_if(|(_, _, size)| num_entries < partition_len || *size == partition.upper)
+            {
+                if num_entries == 0 {
+                    partition.lower = size;
+                }
+                partition.upper = size;
+                for index in &mut partition.indexes {
+                    index.insert(key.clone(), &min_hash)?;
+                }
+                num_entries += 1;
+            }
+            self.partitions.push(partition);
+        }
+        Ok(())
+    }
+
+    /// Query for the keys of indexed sets that likely contain at least a fraction of
+    /// `containment_threshold` of the query set, whose size is `size`
+    pub fn query(
+        &self,
+        min_hash: &MinHash,
+        size: usize,
+        containment_threshold: f64,
+    ) -> Result<HashSet<KeyType>> {
+        if !(0.0..=1.0).contains(&containment_threshold) {
+            return Err(MinHashingError::WrongThresholdInterval);
+        }
+        if min_hash.hash_values.0.len() != self.num_perm {
+            return Err(MinHashingError::DifferentNumPermFuncs);
+        }
+        let mut candidates = HashSet::new();
+        if size == 0 {
+            return Ok(candidates);
+        }
+        for partition in &self.partitions {
+            // Sets smaller than threshold * size cannot reach the containment threshold
+            if (partition.upper as f64) < containment_threshold * size as f64 {
+                continue;
+            }
+            let LshParams { b, r } = self
+                .containment_params(containment_threshold, partition.upper as f64 / size as f64);
+            candidates.extend(partition.indexes[r - 1].query_bands(min_hash, b)?);
+        }
+        Ok(candidates)
+    }
+
+    /// Find the optimal params for a containment threshold and `xq` like
+    /// `find_optimal_containment_params`, reusing the ones of earlier queries
+    fn containment_params(&self, threshold: f64, xq: f64) -> LshParams {
+        let key = (threshold.to_bits(), xq.to_bits());
+        let cached = self
+            .params_cache
+            .0
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .get(&key)
+            .cloned();
+        if let Some(params) = cached {
+            return params;
+        }
+        // The lock is not held while integrating, so concurrent queries may compute the same params
+        let params = find_optimal_containment_params(
+            threshold,
+            self.num_perm,
+            self.max_r,
+            xq,
+            &self.weights,
+        );
+        let mut cache = self
+            .params_cache
+            .0
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        if cache.len() >= _MAX_CACHED_PARAMS {
+            cache.clear();
+        }
+        cache.insert(key, params.clone());
+        params
+    }
+
+    fn new_partition(&self) -> Partition<KeyType> {
+        let indexes = (1..=self.max_r)
+            .map(|r| {
+                let params = LshParams {
+                    b: self.num_perm / r,
+                    r,
+                };
+                MinHashLsh::with_params(self.num_perm, 0.0, self.weights.clone(), params)
+            })
+            .collect();
+        Partition {
+            lower: 0,
+            upper: 0,
+            indexes,
+        }
+    }
+}
+
+/// Find the number of bands and the band size minimizing the weighted false positive and false
+/// negative probabilities for a containment threshold, where `xq` is the ratio between the upper
+/// bound of the set sizes in a partition and the size of the query set
+fn find_optimal_containment_params(
+    threshold: f64,
+    num_perm: usize,
+    max_r: usize,
+    xq: f64,
+    weights: &Weights,
+) -> LshParams {
+    let Weights(false_positive_weight, false_negative_weight) = weights;
+    let mut min_error = f64::INFINITY;
+    let mut opt = LshParams { b: 1, r: 1 };
+    for b in 1..num_perm + 1 {
+        for r in 1..max_r.min(num_perm / b) + 1 {
+            let false_pos = containment_false_positive_probability(threshold, b, r, xq);
+            let false_neg = containment_false_negative_probability(threshold, b, r, xq);
+            let error = false_pos * false_positive_weight + false_neg * false_negative_weight;
+            if error < min_error {
+                min_error = error;
+                opt = LshParams { b, r };
+            }
+        }
+    }
+    opt
+}
+
+fn containment_false_positive_probability(threshold: f64, b: usize, r: usize, xq: f64) -> f64 {
+    // The jaccard similarity corresponding to the containment t is t / (1 + xq - t)
+    let _probability =
+        |t: f64| -> f64 { 1. - f64::powf(1. - f64::powi(t / (1. + xq - t), r as i32), b as f64) };
+    integrate(_probability, 0.0, threshold.min(xq), _ALLOWED_INTEGRATE_ERR).integral
+}
+
+fn containment_false_negative_probability(threshold: f64, b: usize, r: usize, xq: f64) -> f64 {
+    let _probability = |t: f64| -> f64 {
+        1. - (1. - f64::powf(1. - f64::powi(t / (1. + xq - t), r as i32), b as f64))
+    };
+    if xq < threshold {
+        return 0.0;
+    }
+    integrate(_probability, threshold, xq.min(1.0), _ALLOWED_INTEGRATE_ERR).integral
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::minhash::min_hash_of;
+
+    #[test]
+    fn test_new() {
+        assert!(<MinHashLshEnsemble<u32>>::new(64, None, None, None).is_ok());
+        assert!(matches!(
+            <MinHashLshEnsemble<u32>>::new(1, None, Some(1), None),
+            Err(MinHashingError::NumPermFuncsTooLow)
+        ));
+        for (num_part, max_r) in [(Some(0), None), (None, Some(0)), (None, Some(65))] {
+            assert!(matches!(
+                <MinHashLshEnsemble<u32>>::new(64, num_part, max_r, None),
+                Err(MinHashingError::WrongBandParams)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_index() -> Result<()> {
+        let mut ensemble = <MinHashLshEnsemble<u32>>::new(64, Some(4), Some(4), None)?;
+        assert!(ensemble.is_empty());
+        let entries = (0..10)
+            .map(|i| (i, min_hash_of(64, 0..(10 * (i + 1))), 10 * (i as usize + 1)))
+            .collect();
+        ensemble.index(entries)?;
+        assert_eq!(ensemble.partitions.len(), 4);
+        for partition in &ensemble.partitions {
+            assert!(partition.lower <= partition.upper);
+            assert_eq!(partition.indexes.len(), 4);
+        }
+        for pair in ensemble.partitions.windows(2) {
+            assert!(pair[0].upper < pair[1].lower);
+        }
+        assert_eq!(ensemble.partitions[0].lower, 10);
+        assert_eq!(ensemble.partitions[3].upper, 100);
+
+        let result = ensemble.index(vec![(0, <MinHash>::new(32, Some(0)), 1)]);
+        assert!(matches!(
+            result,
+            Err(MinHashingError::DifferentNumPermFuncs)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_query() -> Result<()> {
+        let mut ensemble = <MinHashLshEnsemble<&str>>::new(64, Some(2), None, None)?;
+        ensemble.index(vec![
+            ("large", min_hash_of(64, 0..80), 80),
+            ("small", min_hash_of(64, 0..20), 20),
+            ("disjoint", min_hash_of(64, 5000..5100), 100),
+        ])?;
+        // The query is fully contained in "large", although their jaccard similarity is small
+        let result = ensemble.query(&min_hash_of(64, 10..60), 50, 0.8)?;
+        assert!(result.contains("large"));
+        assert!(!result.contains("small"));
+        assert!(!result.contains("disjoint"));
+
+        assert!(ensemble.query(&min_hash_of(64, 10..60), 0, 0.8)?.is_empty());
+        assert!(matches!(
+            ensemble.query(&min_hash_of(64, 10..60), 50, 1.5),
+            Err(MinHashingError::WrongThresholdInterval)
+        ));
+
+        // The params of both partitions are cached and reused by the same query
+        let num_cached = ensemble.params_cache.0.lock().unwrap().len();
+        assert_eq!(num_cached, 2);
+        assert_eq!(ensemble.query(&min_hash_of(64, 10..60), 50, 0.8)?, result);
+        assert_eq!(ensemble.params_cache.0.lock().unwrap().len(), num_cached);
+        Ok(())
+    }
+
+    #[test]
+    fn test_optimal_containment_params() {
+        let weights = Weights(0.5, 0.5);
+        let LshParams { b, r } = find_optimal_containment_params(0.8, 64, 8, 1.0, &weights);
+        assert!(b * r <= 64);
+        assert!((1..=8).contains(&r));
+        // A much larger indexed set lowers the jaccard similarity, so more bands are needed
+        let LshParams { b: b_large, .. } =
+            find_optimal_containment_params(0.8, 64, 8, 20.0, &weights);
+        assert!(b_large >= b);
+    }
+}
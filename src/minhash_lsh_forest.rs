@@ -0,0 +1,192 @@
+use crate::error::MinHashingError;
+use crate::minhash::MinHash;
+use crate::minhash_lsh::LshParams;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+type Result<T> = std::result::Result<T, MinHashingError>;
+
+/// The MinHashLshForest struct, which answers top-k queries without a fixed threshold by matching
+/// variable-length prefixes of the bands, as described in "LSH Forest: Self-Tuning Indexes for
+/// Similarity Search" by Bawa et al.
+#[derive(Clone)]
+pub struct MinHashLshForest<KeyType: Eq + Hash + Clone> {
+    num_perm: usize,
+    params: LshParams,
+    hash_tables: Vec<HashMap<Vec<u64>, Vec<KeyType>>>,
+    sorted_hash_tables: Vec<Vec<Vec<u64>>>,
+    hash_ranges: Vec<(usize, usize)>,
+    keys: HashSet<KeyType>,
+}
+
+impl<KeyType: Eq + Hash + Clone> MinHashLshForest<KeyType> {
+    /// Build a new MinHashLshForest struct with `num_trees` prefix trees (8 by default), each of
+    /// which covers a band of `num_perm / num_trees` hash values
+    pub fn new(num_perm: usize, num_trees: Option<usize>) -> Result<MinHashLshForest<KeyType>> {
+        let num_trees = num_trees.unwrap_or(8);
+        if num_perm < 2 {
+            return Err(MinHashingError::NumPermFuncsTooLow);
+        }
+        // Every tree needs a band of at least one hash value
+        if num_trees == 0 || num_perm < num_trees {
+            return Err(MinHashingError::WrongBandParams);
+        }
+        let params = LshParams {
+            b: num_trees,
+            r: num_perm / num_trees,
+        };
+        Ok(MinHashLshForest {
+            num_perm,
+            hash_tables: (0..params.b).map(|_| HashMap::new()).collect(),
+            sorted_hash_tables: vec![Vec::new(); params.b],
+            hash_ranges: params.hash_ranges(),
+            params,
+            keys: HashSet::new(),
+        })
+    }
+
+    /// Check whether the MinHashLshForest contains any MinHash structs
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Checks whether a MinHash struct with a specific key is contained in the MinHashLshForest
+    pub fn contains_key(&self, key: &KeyType) -> bool {
+        self.keys.contains(key)
+    }
+
+    /// Insert a new MinHash struct. It can only be found by `query` after calling `index`.
+    pub fn insert(&mut self, key: KeyType, min_hash: &MinHash) -> Result<()> {
+        if min_hash.hash_values.0.len() != self.num_perm {
+            return Err(MinHashingError::DifferentNumPermFuncs);
+        }
+        if self.keys.contains(&key) {
+            return Err(MinHashingError::KeyAlreadyExists);
+        }
+        for ((start, end), table) in self.hash_ranges.iter().zip(&mut self.hash_tables) {
+            table
+                .entry(min_hash.hash_values.0[*start..*end].to_vec())
+                .or_insert_with(Vec::new)
+                .push(key.clone());
+        }
+        self.keys.insert(key);
+        Ok(())
+    }
+
+    /// Sort the bands of all inserted MinHash structs so that they can be queried by prefix
+    pub fn index(&mut self) {
+        for (table, sorted_table) in self.hash_tables.iter().zip(&mut self.sorted_hash_tables) {
+            *sorted_table = table.keys().cloned().collect();
+            sorted_table.sort_unstable();
+        }
+    }
+
+    /// Query for up to `k` candidates that are likely the most similar to the MinHash struct. The
+    /// bands are matched by prefixes of decreasing length until `k` candidates are found.
+    pub fn query(&self, min_hash: &MinHash, k: usize) -> Result<Vec<KeyType>> {
+        if min_hash.hash_values.0.len() != self.num_perm {
+            return Err(MinHashingError::DifferentNumPermFuncs);
+        }
+        let mut seen = HashSet::new();
+        let mut candidates = Vec::new();
+        if k == 0 {
+            return Ok(candidates);
+        }
+        for prefix_len in (1..=self.params.r).rev() {
+            for key in self.query_prefix(min_hash, prefix_len) {
+                if seen.insert(key) {
+                    candidates.push(key.clone());
+                    if candidates.len() == k {
+                        return Ok(candidates);
+                    }
+                }
+            }
+        }
+        Ok(candidates)
+    }
+
+    /// Iterate over the keys in all buckets whose band starts with the same `prefix_len` hash
+    /// values as the corresponding band of the query
+    fn query_prefix<'a>(
+        &'a self,
+        min_hash: &'a MinHash,
+        prefix_len: usize,
+    ) -> impl Iterator<Item = &'a KeyType> + 'a {
+        self.hash_ranges
+            .iter()
+            .zip(&self.sorted_hash_tables)
+            .zip(&self.hash_tables)
+            .flat_map(move |(((start, _), sorted_table), table)| {
+                let prefix = &min_hash.hash_values.0[*start..*start + prefix_len];
+                let first = sorted_table.partition_point(|band| &band[..prefix_len] < prefix);
+                sorted_table[first..]
+                    .iter()
+                    .take_while(move |band| &band[..prefix_len] == prefix)
+                    .flat_map(move |band| &table[band])
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::minhash::min_hash_of;
+
+    #[test]
+    fn test_init() -> Result<()> {
+        let forest = <MinHashLshForest<&str>>::new(128, None)?;
+        assert!(forest.is_empty());
+        assert_eq!(forest.params.b, 8);
+        assert_eq!(forest.params.r, 16);
+        assert!(matches!(
+            <MinHashLshForest<&str>>::new(1, None),
+            Err(MinHashingError::NumPermFuncsTooLow)
+        ));
+        for num_trees in [0, 8] {
+            assert!(matches!(
+                <MinHashLshForest<&str>>::new(4, Some(num_trees)),
+                Err(MinHashingError::WrongBandParams)
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert() -> Result<()> {
+        let mut forest = <MinHashLshForest<&str>>::new(64, Some(4))?;
+        forest.insert("a", &min_hash_of(64, 0..10))?;
+        assert!(forest.contains_key(&"a"));
+        assert!(matches!(
+            forest.insert("a", &min_hash_of(64, 0..10)),
+            Err(MinHashingError::KeyAlreadyExists)
+        ));
+        assert!(matches!(
+            forest.insert("b", &<MinHash>::new(32, Some(0))),
+            Err(MinHashingError::DifferentNumPermFuncs)
+        ));
+        for table in &forest.hash_tables {
+            assert_eq!(table.len(), 1);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_query() -> Result<()> {
+        let mut forest = <MinHashLshForest<u32>>::new(64, Some(4))?;
+        for i in 0..10 {
+            forest.insert(i, &min_hash_of(64, (10 * i)..(100 + 10 * i)))?;
+        }
+        // Not indexed yet
+        assert!(forest.query(&min_hash_of(64, 0..100), 1)?.is_empty());
+
+        forest.index();
+        let result = forest.query(&min_hash_of(64, 0..100), 1)?;
+        assert_eq!(result, vec![0]);
+        let result = forest.query(&min_hash_of(64, 0..100), 3)?;
+        assert_eq!(result.len(), 3);
+        assert!(result.contains(&0));
+        let result = forest.query(&min_hash_of(64, 0..100), 20)?;
+        assert!(result.len() <= 10);
+        Ok(())
+    }
+}
@@ -0,0 +1,239 @@
+use crate::error::MinHashingError;
+use crate::minhash::{HashValues, MinHash};
+use crate::minhash_lsh::MinHashLsh;
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+type Result<T> = std::result::Result<T, MinHashingError>;
+
+impl MinHash {
+    /// Update many MinHash structs in parallel, each with all values of the document at the same
+    /// position. The result is identical to calling `update` for every value of every document.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of MinHash structs and documents differ.
+    pub fn par_update<T, D>(min_hashes: &mut [MinHash], documents: &[D])
+    where
+        T: Hash,
+        D: AsRef<[T]> + Sync,
+    {
+        assert_eq!(
+            min_hashes.len(),
+            documents.len(),
+            "every MinHash struct needs exactly one document"
+        );
+        min_hashes
+            .par_iter_mut()
+            .zip(documents)
+            .for_each(|(min_hash, document)| {
+                for value in document.as_ref() {
+                    min_hash.update(value);
+                }
+            });
+    }
+}
+
+impl<KeyType: Eq + Hash + Clone + Send + Sync> MinHashLsh<KeyType> {
+    /// Run `query` for many MinHash structs in parallel, returning the candidates of each in the
+    /// same order
+    pub fn query_batch(&self, min_hashes: &[MinHash]) -> Result<Vec<HashSet<KeyType>>> {
+        min_hashes
+            .par_iter()
+            .map(|min_hash| {
+                let mut candidates = HashSet::new();
+                self.query_into(min_hash, &mut candidates)?;
+                Ok(candidates)
+            })
+            .collect()
+    }
+
+    /// Insert many MinHash structs, updating the hash tables of all bands in parallel. The result
+    /// is identical to calling `insert` for every entry in order, including the handling of
+    /// duplicate keys. If an entry fails to be inserted, all entries before it are inserted and
+    /// its error is returned. With a maximum bucket size, the entries are inserted one at a time.
+    pub fn insert_batch(&mut self, entries: &[(KeyType, MinHash)]) -> Result<()> {
+        if self.max_bucket_size.is_some() {
+            return entries
+                .iter()
+                .try_for_each(|(key, min_hash)| self.insert(key.clone(), min_hash));
+        }
+
+        // Apply the policies in order, keeping the last accepted entry of every key
+        let mut accepted: Vec<&(KeyType, MinHash)> = Vec::with_capacity(entries.len());
+        let mut positions: HashMap<&KeyType, usize> = HashMap::new();
+        let mut replaced = Vec::new();
+        let mut result = Ok(());
+        for entry in entries {
+            let (key, min_hash) = entry;
+            let position = positions.get(key).copied();
+            let check = match position {
+                Some(_) => self
+                    .check_hash_values(&min_hash.hash_values.0)
+                    .and_then(|_| self.accepts_duplicate()),
+                None => self.check_insert(key, &min_hash.hash_values.0),
+            };
+            match (check, position) {
+                (Err(err), _) => {
+                    result = Err(err);
+                    break;
+                }
+                (Ok(false), _) => {}
+                (Ok(true), Some(position)) => accepted[position] = entry,
+                (Ok(true), None) => {
+                    if self.keys.contains_key(key) {
+                        replaced.push(key);
+                    }
+                    positions.insert(key, accepted.len());
+                    accepted.push(entry);
+                }
+            }
+        }
+        for key in replaced {
+            self.remove(key)?;
+        }
+
+        let hash_value_parts: Vec<_> = accepted
+            .par_iter()
+            .map(|(_, min_hash)| {
+                let hash_values = &min_hash.hash_values.0;
+                let is_empty = self.is_special_empty(hash_values);
+                (self.hash_value_parts(hash_values), is_empty)
+            })
+            .collect();
+        self.hash_tables
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(band, table)| {
+                for ((key, _), (hash_value_parts, is_empty)) in
+                    accepted.iter().zip(&hash_value_parts)
+                {
+                    if !is_empty {
+                        table
+                            .entry(hash_value_parts[band].clone())
+                            .or_default()
+                            .insert(key.clone());
+                    }
+                }
+            });
+
+        if let Some(signatures) = &mut self.signatures {
+            signatures.reserve(accepted.len());
+            for (key, min_hash) in &accepted {
+                signatures.insert(key.clone(), HashValues(min_hash.hash_values.0.clone()));
+            }
+        }
+        self.keys.reserve(accepted.len());
+        for ((key, _), (hash_value_parts, is_empty)) in accepted.into_iter().zip(hash_value_parts) {
+            if is_empty {
+                self.insert_empty_hash_value_parts(key.clone(), hash_value_parts);
+            } else {
+                self.keys.insert(key.clone(), hash_value_parts);
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::minhash_lsh::{DuplicateKeyPolicy, EmptySignaturePolicy};
+
+    fn documents() -> Vec<Vec<u32>> {
+        (0..100).map(|i| (i * 7..i * 7 + 40).collect()).collect()
+    }
+
+    fn min_hashes(documents: &[Vec<u32>]) -> Vec<MinHash> {
+        documents
+            .iter()
+            .map(|document| {
+                let mut min_hash = <MinHash>::new(64, Some(3));
+                for value in document {
+                    min_hash.update(value);
+                }
+                min_hash
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_par_update() {
+        let documents = documents();
+        let mut parallel: Vec<MinHash> = (0..documents.len())
+            .map(|_| <MinHash>::new(64, Some(3)))
+            .collect();
+        MinHash::par_update(&mut parallel, &documents);
+        for (parallel, sequential) in parallel.iter().zip(min_hashes(&documents)) {
+            assert_eq!(parallel.hash_values.0, sequential.hash_values.0);
+        }
+    }
+
+    #[test]
+    fn test_query_batch() -> Result<()> {
+        let min_hashes = min_hashes(&documents());
+        let mut lsh = <MinHashLsh<usize>>::new(64, None, Some(0.5))?;
+        for (key, min_hash) in min_hashes.iter().enumerate() {
+            lsh.insert(key, min_hash)?;
+        }
+        let results = lsh.query_batch(&min_hashes)?;
+        assert_eq!(results.len(), min_hashes.len());
+        for (result, min_hash) in results.into_iter().zip(&min_hashes) {
+            assert_eq!(result, lsh.query(min_hash)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_batch() -> Result<()> {
+        let min_hashes = min_hashes(&documents());
+        let mut entries: Vec<(usize, MinHash)> = min_hashes
+            .iter()
+            .enumerate()
+            .map(|(key, min_hash)| (key % 80, min_hash.clone()))
+            .collect();
+        entries.push((100, <MinHash>::new(64, Some(3))));
+
+        for policy in [DuplicateKeyPolicy::Replace, DuplicateKeyPolicy::Ignore] {
+            let build = || {
+                MinHashLsh::<usize>::builder()
+                    .num_perm(64)
+                    .threshold(0.5)
+                    .duplicate_key_policy(policy)
+                    .empty_signature_policy(EmptySignaturePolicy::Bucket)
+                    .with_signatures()
+                    .build()
+            };
+            let mut sequential = build()?;
+            let mut parallel = build()?;
+            sequential.insert(5, &min_hashes[99])?;
+            parallel.insert(5, &min_hashes[99])?;
+            for (key, min_hash) in &entries {
+                sequential.insert(*key, min_hash)?;
+            }
+            parallel.insert_batch(&entries)?;
+            assert_eq!(parallel.keys, sequential.keys);
+            assert_eq!(parallel.hash_tables, sequential.hash_tables);
+            assert_eq!(parallel.empty_keys, sequential.empty_keys);
+            let signatures = parallel.signatures.as_ref().unwrap();
+            for (key, signature) in sequential.signatures.as_ref().unwrap() {
+                assert_eq!(signatures[key].0, signature.0);
+            }
+        }
+
+        let mut lsh: MinHashLsh<usize> = MinHashLsh::builder()
+            .num_perm(64)
+            .threshold(0.5)
+            .duplicate_key_policy(DuplicateKeyPolicy::Error)
+            .build()?;
+        assert!(matches!(
+            lsh.insert_batch(&entries),
+            Err(MinHashingError::KeyAlreadyExists)
+        ));
+        // The entries before the first duplicate key are inserted
+        assert_eq!(lsh.len(), 80);
+        assert!(lsh.query(&min_hashes[79])?.contains(&79));
+        Ok(())
+    }
+}
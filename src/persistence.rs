@@ -0,0 +1,927 @@
+use crate::error::MinHashingError;
+use crate::minhash::{HashValues, MinHash};
+use crate::minhash_lsh::{
+    BandHashing, BandKey, BucketOverflowPolicy, DuplicateKeyPolicy, EmptySignaturePolicy,
+    LshParams, MinHashLsh, Weights,
+};
+use std::convert::TryInto;
+use std::fs::{self, File, OpenOptions};
+use std::hash::Hash;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const _SNAPSHOT_MAGIC: &[u8; 7] = b"MHLSNAP";
+// Follows the magic and must be changed whenever the layout of the snapshots changes. Snapshots
+// of the first layout ended the magic with b'1'.
+const _SNAPSHOT_VERSION: u8 = b'2';
+const _SNAPSHOT_PREFIX: &str = "snapshot-";
+const _LOG_PREFIX: &str = "wal-";
+// Payload length as u32 followed by the checksum of the payload as u64
+const _RECORD_HEADER_SIZE: usize = 12;
+const _INSERT_RECORD: u8 = 1;
+const _REMOVE_RECORD: u8 = 2;
+
+type Result<T> = std::result::Result<T, MinHashingError>;
+
+/// Keys that can be written to the snapshots and the operation log of a DurableMinHashLsh
+pub trait PersistentKey: Sized {
+    fn to_bytes(&self) -> Vec<u8>;
+    fn from_bytes(bytes: &[u8]) -> Option<Self>;
+}
+
+impl PersistentKey for String {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        String::from_utf8(bytes.to_vec()).ok()
+    }
+}
+
+impl PersistentKey for Vec<u8> {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.clone()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(bytes.to_vec())
+    }
+}
+
+macro_rules! impl_persistent_key_for_int {
+    ($($int:ty),*) => {
+        $(
+            impl PersistentKey for $int {
+                fn to_bytes(&self) -> Vec<u8> {
+                    self.to_le_bytes().to_vec()
+                }
+
+                fn from_bytes(bytes: &[u8]) -> Option<Self> {
+                    Some(<$int>::from_le_bytes(bytes.try_into().ok()?))
+                }
+            }
+        )*
+    };
+}
+
+impl_persistent_key_for_int!(u32, u64, u128, i32, i64, i128);
+
+/// A MinHashLsh whose changes are written to an append-only operation log in a directory before
+/// they are applied. The log is periodically compacted into a snapshot of the whole index, and
+/// `MinHashLsh::recover` restores the index from the latest snapshot and the log. Snapshots keep
+/// the whole configuration set with `MinHashLsh::builder`, including the `buffer_size` of
+/// insertion sessions, and carry a format version, so that snapshots written in an older layout
+/// are rejected with `UnsupportedSnapshotVersion` instead of being misread.
+pub struct DurableMinHashLsh<KeyType: Eq + Hash + Clone + PersistentKey> {
+    lsh: MinHashLsh<KeyType>,
+    dir: PathBuf,
+    generation: u64,
+    log: File,
+    // The length of the valid records in the log, which a failed append is rolled back to
+    log_len: u64,
+    // Set when a failed write could not be rolled back, after which nothing is persisted anymore
+    broken: bool,
+    snapshot_interval: Option<usize>,
+    num_logged: usize,
+    // Makes the next append write only that many bytes of its record and fail
+    #[cfg(test)]
+    torn_append: Option<usize>,
+}
+
+impl<KeyType: Eq + Hash + Clone + PersistentKey> DurableMinHashLsh<KeyType> {
+    /// Start persisting a MinHashLsh into a directory that does not contain an index yet. If a
+    /// `snapshot_interval` is given, a new snapshot is written after that many logged operations.
+    pub fn create<P: AsRef<Path>>(
+        dir: P,
+        lsh: MinHashLsh<KeyType>,
+        snapshot_interval: Option<usize>,
+    ) -> Result<DurableMinHashLsh<KeyType>> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        if latest_generation(&dir)?.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "directory already contains a snapshot",
+            )
+            .into());
+        }
+        write_snapshot(&dir, 0, &lsh)?;
+        let log = open_log(&dir, 0, 0)?;
+        Ok(DurableMinHashLsh {
+            lsh,
+            dir,
+            generation: 0,
+            log,
+            log_len: 0,
+            broken: false,
+            snapshot_interval,
+            num_logged: 0,
+            #[cfg(test)]
+            torn_append: None,
+        })
+    }
+
+    /// Recover the index persisted in a directory and continue logging changes to it. A truncated
+    /// record at the end of the operation log is discarded.
+    pub fn open<P: AsRef<Path>>(
+        dir: P,
+        snapshot_interval: Option<usize>,
+    ) -> Result<DurableMinHashLsh<KeyType>> {
+        let dir = dir.as_ref().to_path_buf();
+        let (lsh, generation, valid_len, num_logged) = recover_from(&dir)?;
+        let log = open_log(&dir, generation, valid_len)?;
+        Ok(DurableMinHashLsh {
+            lsh,
+            dir,
+            generation,
+            log,
+            log_len: valid_len,
+            broken: false,
+            snapshot_interval,
+            num_logged,
+            #[cfg(test)]
+            torn_append: None,
+        })
+    }
+
+    /// The persisted MinHashLsh, e.g. for querying
+    pub fn lsh(&self) -> &MinHashLsh<KeyType> {
+        &self.lsh
+    }
+
+    /// Stop persisting changes and return the MinHashLsh
+    pub fn into_inner(self) -> MinHashLsh<KeyType> {
+        self.lsh
+    }
+
+    /// Log and insert a new MinHash struct. A change whose logging failed is not applied, and the
+    /// log is rolled back to its last complete record. If that fails as well, all further changes
+    /// are rejected, and the index has to be reopened with `open`.
+    pub fn insert(&mut self, key: KeyType, min_hash: &MinHash) -> Result<()> {
+        // Only log insertions that succeed and change the index, so that replaying the log never
+        // fails because of the configured policies
+        if !self.lsh.check_insert(&key, &min_hash.hash_values.0)? {
+            return Ok(());
+        }
+        let mut payload = vec![_INSERT_RECORD];
+        put_bytes(&mut payload, &key.to_bytes());
+        for value in &min_hash.hash_values.0 {
+            payload.extend_from_slice(&value.to_le_bytes());
+        }
+        self.append(&payload)?;
+        self.lsh.insert(key, min_hash)?;
+        self.snapshot_if_due()
+    }
+
+    /// Log and remove a MinHash struct with a specific key
+    pub fn remove(&mut self, key: &KeyType) -> Result<()> {
+        if !self.lsh.contains_key(key) {
+            return Err(MinHashingError::KeyDoesNotExist);
+        }
+        let mut payload = vec![_REMOVE_RECORD];
+        put_bytes(&mut payload, &key.to_bytes());
+        self.append(&payload)?;
+        self.lsh.remove(key)?;
+        self.snapshot_if_due()
+    }
+
+    /// Flush the operation log to disk, so that logged changes also survive an operating system
+    /// crash and not only a crash of the process
+    pub fn sync(&mut self) -> Result<()> {
+        self.log.sync_all()?;
+        Ok(())
+    }
+
+    /// Write a snapshot of the whole index and start a new, empty operation log. If this fails,
+    /// changes continue to be logged to the previous operation log.
+    pub fn snapshot(&mut self) -> Result<()> {
+        self.check_usable()?;
+        let generation = self.generation + 1;
+        // Recovery switches to the new log as soon as the new snapshot exists, so the log has to
+        // be ready before
+        let log = open_log(&self.dir, generation, 0)?;
+        if let Err(err) = write_snapshot(&self.dir, generation, &self.lsh) {
+            // The snapshot may already be in place if only syncing the directory failed
+            if remove_if_exists(&file_path(&self.dir, _SNAPSHOT_PREFIX, generation)).is_err() {
+                self.broken = true;
+            }
+            return Err(err);
+        }
+        let previous_generation = self.generation;
+        self.log = log;
+        self.log_len = 0;
+        self.generation = generation;
+        self.num_logged = 0;
+        remove_if_exists(&file_path(&self.dir, _LOG_PREFIX, previous_generation))?;
+        remove_if_exists(&file_path(&self.dir, _SNAPSHOT_PREFIX, previous_generation))
+    }
+
+    fn append(&mut self, payload: &[u8]) -> Result<()> {
+        self.check_usable()?;
+        let mut record = Vec::with_capacity(_RECORD_HEADER_SIZE + payload.len());
+        record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        record.extend_from_slice(&checksum(payload).to_le_bytes());
+        record.extend_from_slice(payload);
+        if let Err(err) = self.write_record(&record) {
+            // Later records must not follow a partially written one, which recovery would only
+            // accept at the end of the log
+            let rollback = self
+                .log
+                .set_len(self.log_len)
+                .and_then(|_| io::Seek::seek(&mut self.log, io::SeekFrom::Start(self.log_len)));
+            if rollback.is_err() {
+                self.broken = true;
+            }
+            return Err(err.into());
+        }
+        self.log_len += record.len() as u64;
+        self.num_logged += 1;
+        Ok(())
+    }
+
+    fn write_record(&mut self, record: &[u8]) -> io::Result<()> {
+        #[cfg(test)]
+        if let Some(len) = self.torn_append.take() {
+            self.log.write_all(&record[..len])?;
+            return Err(io::Error::other("torn append"));
+        }
+        self.log.write_all(record)
+    }
+
+    fn check_usable(&self) -> Result<()> {
+        if self.broken {
+            return Err(io::Error::other(
+                "a failed write could not be rolled back, reopen the index",
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    fn snapshot_if_due(&mut self) -> Result<()> {
+        match self.snapshot_interval {
+            Some(interval) if self.num_logged >= interval => self.snapshot(),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<KeyType: Eq + Hash + Clone + PersistentKey> MinHashLsh<KeyType> {
+    /// Restore a MinHashLsh persisted by a DurableMinHashLsh by replaying the operation log over
+    /// the latest snapshot. A truncated record at the end of the operation log is ignored.
+    pub fn recover<P: AsRef<Path>>(dir: P) -> Result<MinHashLsh<KeyType>> {
+        let (lsh, _, _, _) = recover_from(dir.as_ref())?;
+        Ok(lsh)
+    }
+}
+
+/// Returns the recovered index, its generation, the length of the valid prefix of the operation
+/// log and the number of records in it
+fn recover_from<KeyType: Eq + Hash + Clone + PersistentKey>(
+    dir: &Path,
+) -> Result<(MinHashLsh<KeyType>, u64, u64, usize)> {
+    let generation = match latest_generation(dir)? {
+        Some(generation) => generation,
+        None => {
+            return Err(
+                io::Error::new(io::ErrorKind::NotFound, "directory contains no snapshot").into(),
+            )
+        }
+    };
+    let mut lsh = read_snapshot(&file_path(dir, _SNAPSHOT_PREFIX, generation))?;
+
+    let log_path = file_path(dir, _LOG_PREFIX, generation);
+    let log = match fs::read(&log_path) {
+        Ok(log) => log,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(err) => return Err(err.into()),
+    };
+    let mut offset = 0;
+    let mut num_records = 0;
+    while log.len() - offset >= _RECORD_HEADER_SIZE {
+        let len = u32::from_le_bytes(log[offset..offset + 4].try_into().unwrap()) as usize;
+        let expected_checksum =
+            u64::from_le_bytes(log[offset + 4..offset + 12].try_into().unwrap());
+        let end = offset + _RECORD_HEADER_SIZE + len;
+        if end > log.len() {
+            // Truncated trailing record
+            break;
+        }
+        let payload = &log[offset + _RECORD_HEADER_SIZE..end];
+        if checksum(payload) != expected_checksum {
+            if end == log.len() {
+                // Torn write of the trailing record
+                break;
+            }
+            return Err(MinHashingError::CorruptedFile(log_path));
+        }
+        replay(&mut lsh, payload)
+            .ok_or_else(|| MinHashingError::CorruptedFile(log_path.clone()))?;
+        offset = end;
+        num_records += 1;
+    }
+    Ok((lsh, generation, offset as u64, num_records))
+}
+
+fn replay<KeyType: Eq + Hash + Clone + PersistentKey>(
+    lsh: &mut MinHashLsh<KeyType>,
+    payload: &[u8],
+) -> Option<()> {
+    let mut reader = ByteReader::new(payload);
+    let op = reader.get_u8()?;
+    let key = KeyType::from_bytes(reader.get_bytes()?)?;
+    match op {
+        _INSERT_RECORD => {
+            let hash_values = (0..lsh.num_perm)
+                .map(|_| reader.get_u64())
+                .collect::<Option<Vec<u64>>>()?;
+            lsh.insert_hash_values(key, &hash_values).ok()
+        }
+        _REMOVE_RECORD => lsh.remove(&key).ok(),
+        _ => None,
+    }
+}
+
+fn write_snapshot<KeyType: Eq + Hash + Clone + PersistentKey>(
+    dir: &Path,
+    generation: u64,
+    lsh: &MinHashLsh<KeyType>,
+) -> Result<()> {
+    let LshParams { b, r } = lsh.params;
+    let Weights(false_positive_weight, false_negative_weight) = lsh.weights;
+    let mut buf = Vec::new();
+    buf.extend_from_slice(_SNAPSHOT_MAGIC);
+    buf.push(_SNAPSHOT_VERSION);
+    for value in &[lsh.num_perm as u64, b as u64, r as u64] {
+        buf.extend_from_slice(&value.to_le_bytes());
+    }
+    for value in &[lsh.threshold, false_positive_weight, false_negative_weight] {
+        buf.extend_from_slice(&value.to_le_bytes());
+    }
+    buf.push(lsh.signatures.is_some() as u8);
+    buf.push(match lsh.duplicate_key_policy {
+        DuplicateKeyPolicy::Error => 0,
+        DuplicateKeyPolicy::Replace => 1,
+        DuplicateKeyPolicy::Ignore => 2,
+    });
+    buf.push(match lsh.band_hashing {
+        BandHashing::Values => 0,
+        BandHashing::Digest64 => 1,
+        BandHashing::Digest128 => 2,
+    });
+    buf.push(match lsh.empty_signature_policy {
+        EmptySignaturePolicy::Allow => 0,
+        EmptySignaturePolicy::Reject => 1,
+        EmptySignaturePolicy::Isolate => 2,
+        EmptySignaturePolicy::Bucket => 3,
+    });
+    let (max_bucket_size, overflow_policy) = match lsh.max_bucket_size {
+        None => (u64::MAX, 0),
+        Some((max_bucket_size, BucketOverflowPolicy::Warn)) => (max_bucket_size as u64, 1),
+        Some((max_bucket_size, BucketOverflowPolicy::Error)) => (max_bucket_size as u64, 2),
+    };
+    buf.extend_from_slice(&max_bucket_size.to_le_bytes());
+    buf.push(overflow_policy);
+    buf.extend_from_slice(&(lsh.buffer_size as u64).to_le_bytes());
+    buf.extend_from_slice(&(lsh.keys.len() as u64).to_le_bytes());
+    for (key, hash_value_parts) in &lsh.keys {
+        put_bytes(&mut buf, &key.to_bytes());
+        buf.push(lsh.empty_keys.contains(key) as u8);
+        for hash_value_part in hash_value_parts {
+            for value in hash_value_part.as_slice() {
+                buf.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        if let Some(signatures) = &lsh.signatures {
+            match signatures.get(key) {
+                Some(signature) => {
+                    buf.push(1);
+                    for value in &signature.0 {
+                        buf.extend_from_slice(&value.to_le_bytes());
+                    }
+                }
+                None => buf.push(0),
+            }
+        }
+    }
+    let checksum = checksum(&buf);
+    buf.extend_from_slice(&checksum.to_le_bytes());
+
+    let path = file_path(dir, _SNAPSHOT_PREFIX, generation);
+    let tmp_path = path.with_extension("tmp");
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(&buf)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, &path)?;
+    sync_dir(dir)
+}
+
+fn read_snapshot<KeyType: Eq + Hash + Clone + PersistentKey>(
+    path: &Path,
+) -> Result<MinHashLsh<KeyType>> {
+    let buf = fs::read(path)?;
+    if buf.starts_with(_SNAPSHOT_MAGIC)
+        && buf.get(_SNAPSHOT_MAGIC.len()) != Some(&_SNAPSHOT_VERSION)
+    {
+        return Err(MinHashingError::UnsupportedSnapshotVersion(
+            path.to_path_buf(),
+        ));
+    }
+    parse_snapshot(&buf).ok_or_else(|| MinHashingError::CorruptedFile(path.to_path_buf()))
+}
+
+fn parse_snapshot<KeyType: Eq + Hash + Clone + PersistentKey>(
+    buf: &[u8],
+) -> Option<MinHashLsh<KeyType>> {
+    let content_len = buf.len().checked_sub(8)?;
+    let expected_checksum = u64::from_le_bytes(buf[content_len..].try_into().ok()?);
+    if checksum(&buf[..content_len]) != expected_checksum {
+        return None;
+    }
+    let mut reader = ByteReader::new(&buf[..content_len]);
+    if reader.take(_SNAPSHOT_MAGIC.len())? != _SNAPSHOT_MAGIC
+        || reader.get_u8()? != _SNAPSHOT_VERSION
+    {
+        return None;
+    }
+    let num_perm = reader.get_u64()? as usize;
+    let b = reader.get_u64()? as usize;
+    let r = reader.get_u64()? as usize;
+    let threshold = reader.get_f64()?;
+    let weights = Weights(reader.get_f64()?, reader.get_f64()?);
+    let mut lsh = MinHashLsh::with_params(num_perm, threshold, weights, LshParams { b, r });
+    let store_signatures = reader.get_u8()? == 1;
+    if store_signatures {
+        lsh = lsh.with_signatures();
+    }
+    lsh.duplicate_key_policy = match reader.get_u8()? {
+        0 => DuplicateKeyPolicy::Error,
+        1 => DuplicateKeyPolicy::Replace,
+        2 => DuplicateKeyPolicy::Ignore,
+        _ => return None,
+    };
+    lsh.band_hashing = match reader.get_u8()? {
+        0 => BandHashing::Values,
+        1 => BandHashing::Digest64,
+        2 => BandHashing::Digest128,
+        _ => return None,
+    };
+    lsh.empty_signature_policy = match reader.get_u8()? {
+        0 => EmptySignaturePolicy::Allow,
+        1 => EmptySignaturePolicy::Reject,
+        2 => EmptySignaturePolicy::Isolate,
+        3 => EmptySignaturePolicy::Bucket,
+        _ => return None,
+    };
+    let max_bucket_size = reader.get_u64()?;
+    lsh.max_bucket_size = match reader.get_u8()? {
+        0 => None,
+        1 => Some((max_bucket_size as usize, BucketOverflowPolicy::Warn)),
+        2 => Some((max_bucket_size as usize, BucketOverflowPolicy::Error)),
+        _ => return None,
+    };
+    lsh.buffer_size = reader.get_u64()? as usize;
+    let num_keys = reader.get_u64()?;
+    for _ in 0..num_keys {
+        let key = KeyType::from_bytes(reader.get_bytes()?)?;
+        let is_empty = match reader.get_u8()? {
+            0 => false,
+            1 => true,
+            _ => return None,
+        };
+        let hash_value_parts = (0..b)
+            .map(|_| match lsh.band_hashing {
+                BandHashing::Values => {
+                    let values = (0..r)
+                        .map(|_| reader.get_u64())
+                        .collect::<Option<Vec<u64>>>()?;
+                    Some(BandKey::Values(values))
+                }
+                BandHashing::Digest64 => Some(BandKey::Digest64([reader.get_u64()?])),
+                BandHashing::Digest128 => {
+                    Some(BandKey::Digest128([reader.get_u64()?, reader.get_u64()?]))
+                }
+            })
+            .collect::<Option<Vec<BandKey>>>()?;
+        if store_signatures && reader.get_u8()? == 1 {
+            let signature = (0..num_perm)
+                .map(|_| reader.get_u64())
+                .collect::<Option<Vec<u64>>>()?;
+            lsh.signatures
+                .as_mut()?
+                .insert(key.clone(), HashValues(signature));
+        }
+        if is_empty {
+            lsh.insert_empty_hash_value_parts(key, hash_value_parts);
+        } else {
+            lsh.insert_hash_value_parts(key, hash_value_parts);
+        }
+    }
+    Some(lsh)
+}
+
+fn open_log(dir: &Path, generation: u64, valid_len: u64) -> Result<File> {
+    let path = file_path(dir, _LOG_PREFIX, generation);
+    let mut log = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)?;
+    // Drop a truncated trailing record so that new records are appended to the valid prefix
+    log.set_len(valid_len)?;
+    log.sync_all()?;
+    sync_dir(dir)?;
+    io::Seek::seek(&mut log, io::SeekFrom::End(0))?;
+    Ok(log)
+}
+
+fn latest_generation(dir: &Path) -> Result<Option<u64>> {
+    let mut latest = None;
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let generation = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(_SNAPSHOT_PREFIX))
+            .and_then(|generation| generation.parse::<u64>().ok());
+        if let Some(generation) = generation {
+            latest = latest.max(Some(generation));
+        }
+    }
+    Ok(latest)
+}
+
+fn file_path(dir: &Path, prefix: &str, generation: u64) -> PathBuf {
+    dir.join(format!("{}{:020}", prefix, generation))
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> Result<()> {
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> Result<()> {
+    Ok(())
+}
+
+fn put_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    buf.extend_from_slice(bytes);
+}
+
+// 64-bit FNV-1a, only used to detect torn writes and corrupted files
+fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(bytes: &'a [u8]) -> ByteReader<'a> {
+        ByteReader { bytes, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let end = self.pos.checked_add(len)?;
+        let bytes = self.bytes.get(self.pos..end)?;
+        self.pos = end;
+        Some(bytes)
+    }
+
+    fn get_u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn get_u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn get_f64(&mut self) -> Option<f64> {
+        Some(f64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn get_bytes(&mut self) -> Option<&'a [u8]> {
+        let len = u32::from_le_bytes(self.take(4)?.try_into().ok()?);
+        self.take(len as usize)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::minhash::min_hash_of;
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let path = std::env::temp_dir().join(format!(
+                "datasketch-minhash-lsh-{}-{}",
+                std::process::id(),
+                name
+            ));
+            let _ = fs::remove_dir_all(&path);
+            TempDir(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_recover_from_log() -> Result<()> {
+        let dir = TempDir::new("test_recover_from_log");
+        let lsh = <MinHashLsh<String>>::new(16, None, Some(0.5))?;
+        let mut durable = DurableMinHashLsh::create(&dir.0, lsh, None)?;
+        for value in &["a", "b", "c"] {
+            durable.insert(value.to_string(), &min_hash_of(16, [value]))?;
+        }
+        durable.remove(&"b".to_string())?;
+        durable.sync()?;
+
+        let recovered = <MinHashLsh<String>>::recover(&dir.0)?;
+        assert_eq!(recovered.keys, durable.lsh().keys);
+        assert!(recovered.contains_key(&"a".to_string()));
+        assert!(!recovered.contains_key(&"b".to_string()));
+        let query = min_hash_of(16, ["c"]);
+        assert!(recovered.query(&query)?.contains("c"));
+        assert_eq!(recovered.query(&query)?, durable.lsh().query(&query)?);
+        assert_eq!(recovered.params.b, durable.lsh().params.b);
+        Ok(())
+    }
+
+    #[test]
+    fn test_recover_from_snapshot_and_log() -> Result<()> {
+        let dir = TempDir::new("test_recover_from_snapshot_and_log");
+        let lsh = <MinHashLsh<u64>>::new(16, None, Some(0.5))?;
+        let mut durable = DurableMinHashLsh::create(&dir.0, lsh, Some(3))?;
+        for key in 0..10 {
+            durable.insert(key, &min_hash_of(16, [key.to_string()]))?;
+        }
+        durable.remove(&4)?;
+        assert_eq!(durable.generation, 3);
+        assert_eq!(fs::read_dir(&dir.0)?.count(), 2);
+
+        let recovered = <MinHashLsh<u64>>::recover(&dir.0)?;
+        assert_eq!(recovered.keys, durable.lsh().keys);
+        Ok(())
+    }
+
+    #[test]
+    fn test_recover_signatures() -> Result<()> {
+        let dir = TempDir::new("test_recover_signatures");
+        let lsh = <MinHashLsh<String>>::new(16, None, Some(0.5))?.with_signatures();
+        let mut durable = DurableMinHashLsh::create(&dir.0, lsh, Some(2))?;
+        for value in &["a", "b", "c"] {
+            durable.insert(value.to_string(), &min_hash_of(16, [value]))?;
+        }
+
+        let recovered = <MinHashLsh<String>>::recover(&dir.0)?;
+        let signatures = recovered.signatures.as_ref().unwrap();
+        assert_eq!(signatures.len(), 3);
+        assert_eq!(signatures["a"].0, min_hash_of(16, ["a"]).hash_values.0);
+        assert_eq!(signatures["c"].0, min_hash_of(16, ["c"]).hash_values.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_recover_duplicate_keys() -> Result<()> {
+        let dir = TempDir::new("test_recover_duplicate_keys");
+        let lsh = <MinHashLsh<String>>::new(16, None, Some(0.5))?;
+        let mut durable = DurableMinHashLsh::create(&dir.0, lsh, None)?;
+        durable.insert("a".to_string(), &min_hash_of(16, ["a"]))?;
+        durable.insert("a".to_string(), &min_hash_of(16, ["b"]))?;
+        assert!(durable.lsh().query(&min_hash_of(16, ["b"]))?.contains("a"));
+        let recovered = <MinHashLsh<String>>::recover(&dir.0)?;
+        assert!(recovered.query(&min_hash_of(16, ["b"]))?.contains("a"));
+        assert!(!recovered.query(&min_hash_of(16, ["a"]))?.contains("a"));
+
+        let dir = TempDir::new("test_recover_duplicate_keys_error");
+        let lsh = MinHashLsh::builder()
+            .num_perm(16)
+            .threshold(0.5)
+            .duplicate_key_policy(DuplicateKeyPolicy::Error)
+            .build()?;
+        let mut durable = DurableMinHashLsh::create(&dir.0, lsh, None)?;
+        durable.insert("a".to_string(), &min_hash_of(16, ["a"]))?;
+        assert!(matches!(
+            durable.insert("a".to_string(), &min_hash_of(16, ["b"])),
+            Err(MinHashingError::KeyAlreadyExists)
+        ));
+        let recovered = <MinHashLsh<String>>::recover(&dir.0)?;
+        assert_eq!(recovered.duplicate_key_policy(), DuplicateKeyPolicy::Error);
+        assert!(recovered.query(&min_hash_of(16, ["a"]))?.contains("a"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_recover_band_digests() -> Result<()> {
+        for band_hashing in [BandHashing::Digest64, BandHashing::Digest128] {
+            let dir = TempDir::new(&format!("test_recover_band_digests_{:?}", band_hashing));
+            let lsh = MinHashLsh::builder()
+                .num_perm(16)
+                .threshold(0.5)
+                .band_hashing(band_hashing)
+                .build()?;
+            let mut durable = DurableMinHashLsh::create(&dir.0, lsh, Some(2))?;
+            for value in &["a", "b", "c"] {
+                durable.insert(value.to_string(), &min_hash_of(16, [value]))?;
+            }
+            let recovered = <MinHashLsh<String>>::recover(&dir.0)?;
+            assert_eq!(recovered.band_hashing(), band_hashing);
+            assert_eq!(recovered.keys, durable.lsh().keys);
+            assert!(recovered.query(&min_hash_of(16, ["b"]))?.contains("b"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_recover_empty_signatures() -> Result<()> {
+        let dir = TempDir::new("test_recover_empty_signatures");
+        let lsh = MinHashLsh::builder()
+            .num_perm(16)
+            .threshold(0.5)
+            .empty_signature_policy(EmptySignaturePolicy::Bucket)
+            .max_bucket_size(1, BucketOverflowPolicy::Error)
+            .build()?;
+        let mut durable = DurableMinHashLsh::create(&dir.0, lsh, Some(3))?;
+        let empty = <MinHash>::new(16, Some(1));
+        for value in &["a", "b"] {
+            durable.insert(value.to_string(), &empty)?;
+        }
+        durable.insert("c".to_string(), &min_hash_of(16, ["c"]))?;
+        // Rejected insertions are not logged
+        assert!(matches!(
+            durable.insert("d".to_string(), &min_hash_of(16, ["c"])),
+            Err(MinHashingError::BucketSizeExceeded)
+        ));
+        durable.insert("e".to_string(), &empty)?;
+
+        let recovered = <MinHashLsh<String>>::recover(&dir.0)?;
+        assert_eq!(recovered.len(), 4);
+        assert_eq!(
+            recovered.empty_signature_policy(),
+            EmptySignaturePolicy::Bucket
+        );
+        assert_eq!(
+            recovered.max_bucket_size,
+            Some((1, BucketOverflowPolicy::Error))
+        );
+        assert_eq!(recovered.query(&empty)?.len(), 3);
+        assert_eq!(recovered.stats().num_empty_keys, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_recover_unbanded_hash_values() -> Result<()> {
+        // The bands cover only the first half of the hash values, so a MinHash struct that agrees
+        // with an empty one in these values but not in the others has the same band parts
+        let dir = TempDir::new("test_recover_unbanded_hash_values");
+        let lsh = MinHashLsh::builder()
+            .num_perm(16)
+            .bands(2, 4)
+            .empty_signature_policy(EmptySignaturePolicy::Isolate)
+            .build()?;
+        let mut durable = DurableMinHashLsh::create(&dir.0, lsh, None)?;
+        let empty = <MinHash>::new(16, Some(1));
+        let mut hash_values = empty.hash_values.0.clone();
+        hash_values[8..].iter_mut().for_each(|value| *value = 1);
+        let half_empty = MinHash::from_hash_values(HashValues(hash_values), Some(1));
+        durable.insert("a".to_string(), &empty)?;
+        durable.insert("b".to_string(), &half_empty)?;
+        durable.insert("c".to_string(), &half_empty)?;
+        durable.snapshot()?;
+
+        let recovered = <MinHashLsh<String>>::recover(&dir.0)?;
+        assert_eq!(recovered.stats().num_empty_keys, 1);
+        assert_eq!(recovered.query(&half_empty)?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot_version() -> Result<()> {
+        let dir = TempDir::new("test_snapshot_version");
+        let lsh = MinHashLsh::builder()
+            .num_perm(16)
+            .threshold(0.5)
+            .buffer_size(7)
+            .build()?;
+        let mut durable = DurableMinHashLsh::create(&dir.0, lsh, None)?;
+        durable.insert("a".to_string(), &min_hash_of(16, ["a"]))?;
+        let recovered = <MinHashLsh<String>>::recover(&dir.0)?;
+        assert_eq!(recovered.buffer_size, 7);
+        drop(durable);
+
+        let snapshot_path = file_path(&dir.0, _SNAPSHOT_PREFIX, 0);
+        let mut snapshot = fs::read(&snapshot_path)?;
+        snapshot[_SNAPSHOT_MAGIC.len()] = b'1';
+        fs::write(&snapshot_path, &snapshot)?;
+        let result = <MinHashLsh<String>>::recover(&dir.0);
+        assert!(matches!(
+            result,
+            Err(MinHashingError::UnsupportedSnapshotVersion(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_append() -> Result<()> {
+        let dir = TempDir::new("test_failed_append");
+        let lsh = <MinHashLsh<String>>::new(16, None, Some(0.5))?;
+        let mut durable = DurableMinHashLsh::create(&dir.0, lsh, None)?;
+        durable.insert("a".to_string(), &min_hash_of(16, ["a"]))?;
+        durable.torn_append = Some(5);
+        assert!(durable
+            .insert("b".to_string(), &min_hash_of(16, ["b"]))
+            .is_err());
+        assert!(!durable.lsh().contains_key(&"b".to_string()));
+        // The partial record was rolled back, so the following ones can be recovered
+        durable.insert("c".to_string(), &min_hash_of(16, ["c"]))?;
+
+        let recovered = <MinHashLsh<String>>::recover(&dir.0)?;
+        assert_eq!(recovered.len(), 2);
+        assert!(recovered.contains_key(&"a".to_string()));
+        assert!(recovered.contains_key(&"c".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_snapshot() -> Result<()> {
+        let dir = TempDir::new("test_failed_snapshot");
+        let lsh = <MinHashLsh<String>>::new(16, None, Some(0.5))?;
+        let mut durable = DurableMinHashLsh::create(&dir.0, lsh, None)?;
+        durable.insert("a".to_string(), &min_hash_of(16, ["a"]))?;
+        // A directory in place of the next log makes opening it fail
+        fs::create_dir(file_path(&dir.0, _LOG_PREFIX, 1))?;
+        assert!(durable.snapshot().is_err());
+        assert!(!file_path(&dir.0, _SNAPSHOT_PREFIX, 1).exists());
+        // Changes are still logged where recovery finds them
+        durable.insert("b".to_string(), &min_hash_of(16, ["b"]))?;
+
+        let recovered = <MinHashLsh<String>>::recover(&dir.0)?;
+        assert_eq!(recovered.len(), 2);
+        assert!(recovered.contains_key(&"b".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncated_trailing_record() -> Result<()> {
+        let dir = TempDir::new("test_truncated_trailing_record");
+        let lsh = <MinHashLsh<String>>::new(16, None, Some(0.5))?;
+        let mut durable = DurableMinHashLsh::create(&dir.0, lsh, None)?;
+        durable.insert("a".to_string(), &min_hash_of(16, ["a"]))?;
+        durable.insert("b".to_string(), &min_hash_of(16, ["b"]))?;
+        drop(durable);
+
+        let log_path = file_path(&dir.0, _LOG_PREFIX, 0);
+        let log = fs::read(&log_path)?;
+        fs::write(&log_path, &log[..log.len() - 5])?;
+
+        let mut durable = <DurableMinHashLsh<String>>::open(&dir.0, None)?;
+        assert!(durable.lsh().contains_key(&"a".to_string()));
+        assert!(!durable.lsh().contains_key(&"b".to_string()));
+        durable.insert("c".to_string(), &min_hash_of(16, ["c"]))?;
+        drop(durable);
+
+        let recovered = <MinHashLsh<String>>::recover(&dir.0)?;
+        assert!(recovered.contains_key(&"a".to_string()));
+        assert!(recovered.contains_key(&"c".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupted_record() -> Result<()> {
+        let dir = TempDir::new("test_corrupted_record");
+        let lsh = <MinHashLsh<String>>::new(16, None, Some(0.5))?;
+        let mut durable = DurableMinHashLsh::create(&dir.0, lsh, None)?;
+        durable.insert("a".to_string(), &min_hash_of(16, ["a"]))?;
+        durable.insert("b".to_string(), &min_hash_of(16, ["b"]))?;
+        drop(durable);
+
+        let log_path = file_path(&dir.0, _LOG_PREFIX, 0);
+        let mut log = fs::read(&log_path)?;
+        log[_RECORD_HEADER_SIZE + 2] ^= 0xff;
+        fs::write(&log_path, &log)?;
+
+        let result = <MinHashLsh<String>>::recover(&dir.0);
+        assert!(matches!(result, Err(MinHashingError::CorruptedFile(_))));
+        Ok(())
+    }
+}